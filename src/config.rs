@@ -0,0 +1,255 @@
+use regex::Regex;
+
+use error::*;
+use fields::{FieldFormat, FieldMatcher};
+use filter::Filter;
+use logparser::{LogParser, ToMatcher};
+use tc::TcTool;
+use template;
+
+/// How many hours/batches a monitor reads by default.
+pub const DEFAULT_COUNT: usize = 6;
+
+/// `MonitorConfig` is a `[name]` section of a config file.
+#[derive(Debug, Default)]
+struct MonitorConfig {
+    name: String,
+    template: Option<String>,
+    paths: Vec<String>,
+    regex: Option<String>,
+    fields: Option<String>,
+    select: Vec<(String, String)>,
+    capture: Vec<(String, String)>,
+    batch: Option<String>,
+    start: Option<String>,
+    filter: Option<String>,
+    count: Option<usize>,
+}
+
+fn regex(value: &str) -> Result<Regex> {
+    Regex::new(value).map_err(|e| LogError::InvalidConfig(e.to_string()))
+}
+
+impl MonitorConfig {
+    /// The monitor starts from the template, if any, the other keys override it.
+    fn into_monitor<'a>(self, prod: bool) -> Result<TcTool<'a>> {
+        let invalid = |reason: &str| LogError::InvalidConfig(format!("[{}] {}", self.name, reason));
+        let template = match self.template {
+            Some(ref t) => Some(template::find(t).ok_or_else(|| invalid("unknown template"))?),
+            None => None,
+        };
+        if self.fields.is_none() && !(self.select.is_empty() && self.capture.is_empty()) {
+            return Err(invalid("select and capture require fields"));
+        }
+        let line = match (&self.regex, &self.fields, template) {
+            (Some(_), Some(_), _) => return Err(invalid("expects either a regex or fields")),
+            (Some(r), None, _) => regex(r)?.to_matcher(),
+            (None, Some(f), _) => {
+                let format = f.parse::<FieldFormat>().map_err(|e| invalid(&e.to_string()))?;
+                let mut matcher = FieldMatcher::new(format);
+                for (field, value) in &self.select {
+                    matcher = matcher.select(field, value);
+                }
+                for (name, field) in &self.capture {
+                    matcher = matcher.capture(name, field);
+                }
+                matcher.to_matcher()
+            }
+            (None, None, Some(t)) => regex(t.line)?.to_matcher(),
+            (None, None, None) => return Err(invalid("requires a template, a regex or fields")),
+        };
+        let batch = match (&self.batch, template) {
+            (Some(b), _) => Some(regex(b)?),
+            (None, Some(t)) => t.batch.map(regex).transpose()?,
+            (None, None) => None,
+        };
+        let parser = match self.start {
+            Some(ref start) => LogParser::new_pair(regex(start)?, line),
+            None => LogParser::new_batch(line, batch),
+        };
+        let paths = match (self.paths.is_empty(), template) {
+            (false, _) => self.paths.clone(),
+            (true, Some(t)) => vec![t.path(prod).to_owned()],
+            (true, None) => return Err(invalid("requires a template or a path")),
+        };
+        let count = self.count.unwrap_or(DEFAULT_COUNT);
+        let mut tc = TcTool::new(self.name.clone(), paths, parser, count);
+        if let Some(ref filter) = self.filter {
+            tc.set_filter(filter.parse::<Filter>().map_err(|e| invalid(&e.to_string()))?);
+        }
+        Ok(tc)
+    }
+}
+
+/// parse reads the monitors of a config file, for example:
+///
+/// ```text
+/// # the template with the prod log files
+/// [Publisher]
+/// template = V1Publisher
+/// path = /var/log/publisher/publisher.log*
+///
+/// [Orders]
+/// start = received order=(?P<id>\d+)
+/// regex = committed order=(?P<id>\d+)
+/// path = /var/log/orders/*.log
+/// filter = NOT "dry-run"
+/// count = 24
+///
+/// # a JSON log instead of a regex
+/// [Indexer]
+/// fields = json
+/// select = event=indexed
+/// capture = watermark=docWriteTime
+/// path = /var/log/indexer/indexer.log
+/// ```
+///
+/// The keys are `template`, `path` (can be repeated), `regex`, `batch`, `start`, `filter` and
+/// `count`.
+/// `fields` (json or logfmt) matches the lines by their fields instead of `regex`, with
+/// `select = field=value` and `capture = name=field`, both can be repeated.
+pub fn parse<'a>(text: &str, prod: bool) -> Result<Vec<TcTool<'a>>> {
+    let mut configs: Vec<MonitorConfig> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let invalid = |reason: &str| {
+            LogError::InvalidConfig(format!("line {}: {}", number + 1, reason))
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            configs.push(MonitorConfig {
+                name: line[1..line.len() - 1].trim().to_owned(),
+                ..MonitorConfig::default()
+            });
+            continue;
+        }
+        let config = configs.last_mut().ok_or_else(|| invalid("expects a [name] first"))?;
+        let mut pair = line.splitn(2, '=');
+        let key = pair.next().unwrap_or("").trim();
+        let value = pair.next().ok_or_else(|| invalid("expects key = value"))?.trim().to_owned();
+        let field_pair = || {
+            let mut pair = value.splitn(2, '=');
+            match (pair.next(), pair.next()) {
+                (Some(name), Some(field)) => Ok((name.trim().to_owned(), field.trim().to_owned())),
+                _ => Err(invalid("expects name=value")),
+            }
+        };
+        match key {
+            "template" => config.template = Some(value),
+            "path" => config.paths.push(value),
+            "regex" => config.regex = Some(value),
+            "fields" => config.fields = Some(value),
+            "select" => config.select.push(field_pair()?),
+            "capture" => config.capture.push(field_pair()?),
+            "batch" => config.batch = Some(value),
+            "start" => config.start = Some(value),
+            "filter" => config.filter = Some(value),
+            "count" => config.count = Some(value.parse().map_err(|_| invalid("invalid count"))?),
+            _ => return Err(invalid(&format!("unknown key {}", key))),
+        }
+    }
+    configs.into_iter().map(|c| c.into_monitor(prod)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_config() {
+        let monitors = parse("# monitors
+[Publisher]
+template = v1publisher
+path = logs/a.log*
+path = logs/b.log*
+
+[Orders]
+start = received order=(?P<id>\\d+)
+regex = committed order=(?P<id>\\d+)
+path = logs/orders.log
+count = 24
+
+[Trimmer]
+template = NgTrimmer
+",
+                             true)
+                           .unwrap();
+        let names: Vec<_> = monitors.iter().map(|tc| tc.name()).collect();
+        assert_eq!(names, ["Publisher", "Orders", "Trimmer"]);
+        assert_eq!(monitors[0].paths(), ["logs/a.log*", "logs/b.log*"]);
+        assert_eq!(monitors[1].count(), 24);
+        assert_eq!(monitors[2].paths(), ["xxx/prod/logs/ng_trimmer.log*"]);
+    }
+
+    #[test]
+    fn can_parse_field_monitors() {
+        let monitors = parse("[Indexer]
+fields = logfmt
+select = event=indexed
+capture = watermark=written
+path = logs/indexer.log
+",
+                             false)
+                           .unwrap();
+        let mut parser = monitors.into_iter().next().unwrap().into_parser();
+        let line = |event| {
+            format!("2015-11-09 02:01:03,100 event={} written=\"2015-11-09 02:01:00\"", event)
+        };
+        parser.process_line(&line("indexed"));
+        parser.process_line(&line("skipped"));
+        assert_eq!(parser.counts(), [(2015110902, 0, 1, 1)]);
+        assert_eq!(parser.stats()[0].1.last_time_stamp, "2015-11-09 02:01:00");
+    }
+
+    #[test]
+    fn can_filter_every_monitor() {
+        let monitors = parse(r#"[Committed]
+regex = committed
+filter = NOT "dry-run"
+path = logs/a.log
+
+[DryRun]
+regex = committed
+filter = "dry-run" AND level >= WARN
+path = logs/a.log
+"#,
+                             false)
+                           .unwrap();
+        let lines = ["2015-11-09 02:01:03,100 INFO committed",
+                     "2015-11-09 02:01:04,100 INFO committed dry-run",
+                     "2015-11-09 02:01:05,100 WARN committed dry-run"];
+        let counts: Vec<_> = monitors.into_iter()
+                                     .map(|tc| {
+                                         let mut parser = tc.into_parser();
+                                         for line in &lines {
+                                             parser.process_line(line);
+                                         }
+                                         parser.counts()
+                                     })
+                                     .collect();
+        assert_eq!(counts, [[(2015110902, 0, 1, 1)], [(2015110902, 0, 1, 1)]]);
+    }
+
+    #[test]
+    fn can_reject_invalid_config() {
+        let error = |text: &str| parse(text, false).err().unwrap().to_string();
+        assert_eq!(error("path = a.log"), "Invalid Config: line 1: expects a [name] first");
+        assert_eq!(error("[A]\nregex"), "Invalid Config: line 2: expects key = value");
+        assert_eq!(error("[A]\nfoo = 1"), "Invalid Config: line 2: unknown key foo");
+        assert_eq!(error("[A]\npath = a.log"),
+                   "Invalid Config: [A] requires a template, a regex or fields");
+        assert_eq!(error("[A]\nfields = xml\npath = a.log"),
+                   "Invalid Config: [A] Invalid Field Format: xml");
+        assert_eq!(error("[A]\nfields = json\nregex = a\npath = a.log"),
+                   "Invalid Config: [A] expects either a regex or fields");
+        assert_eq!(error("[A]\nregex = a\nselect = event=a\npath = a.log"),
+                   "Invalid Config: [A] select and capture require fields");
+        assert_eq!(error("[A]\nregex = a\nfilter = (\"a\"\npath = a.log"),
+                   "Invalid Config: [A] Invalid Filter: expects ), found None");
+        assert_eq!(error("[A]\ncapture = watermark"), "Invalid Config: line 2: expects name=value");
+        assert_eq!(error("[A]\ntemplate = nothing"), "Invalid Config: [A] unknown template");
+        assert!(error("[A]\nregex = (\npath = a.log").starts_with("Invalid Config: "));
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hasher;
+use fnv::FnvHasher;
+
+use inthash::IntBuildHasher;
+
+/// Longest window, in seconds, deduplicated with a set of the id hashes. Longer windows use bloom
+/// filters so the memory doesn't grow with the number of ids.
+const SET_WINDOW: i64 = 3600;
+
+/// Bits of every bloom filter, 1MB. About 1.8% false positives per filter with a million ids per
+/// window, and as an id is looked up in both filters, about 3.6% false duplicates.
+const BLOOM_BITS: usize = 1 << 23;
+
+/// Hash functions of the bloom filters.
+const BLOOM_HASHES: usize = 5;
+
+/// `Dedup` tells whether an id was already seen within the window, by the time of the lines.
+/// The window follows the order the lines are read, ids are forgotten once the lines are more
+/// than a window away from them.
+pub enum Dedup {
+    /// for the short windows, 64-bit hash of the id -> time seen. Two ids are only mixed up by a
+    /// hash collision, less than one chance in 10^7 with a million ids in the window
+    Set {
+        window: i64,
+        seen: HashMap<u64, i64, IntBuildHasher>,
+        order: VecDeque<(i64, u64)>,
+    },
+    /// the ids of the current and the previous window, a false duplicate is possible. The
+    /// windows are fixed, so an id is remembered for 1 to 2 times the configured window, depending
+    /// on where it falls in the current one
+    Bloom {
+        window: i64,
+        start: Option<i64>,
+        current: Vec<u64>,
+        previous: Vec<u64>,
+    },
+}
+
+fn hash_id(id: &str) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(id.as_bytes());
+    hasher.finish()
+}
+
+/// the bit positions of the id in a bloom filter, by double hashing
+fn bloom_bits(hash: u64) -> [usize; BLOOM_HASHES] {
+    let (h1, h2) = (hash, hash.rotate_left(32) | 1);
+    ::std::array::from_fn(|i| {
+        (h1.wrapping_add((i as u64).wrapping_mul(h2)) % BLOOM_BITS as u64) as usize
+    })
+}
+
+fn bloom_contains(filter: &[u64], bits: &[usize]) -> bool {
+    bits.iter().all(|b| filter[b / 64] & (1 << (b % 64)) != 0)
+}
+
+impl Dedup {
+    /// window is in seconds
+    pub fn new(window: i64) -> Dedup {
+        if window <= SET_WINDOW {
+            Dedup::Set {
+                window,
+                seen: HashMap::default(),
+                order: VecDeque::new(),
+            }
+        } else {
+            Dedup::Bloom {
+                window,
+                start: None,
+                current: vec![0; BLOOM_BITS / 64],
+                previous: vec![0; BLOOM_BITS / 64],
+            }
+        }
+    }
+
+    /// contains tells whether the id seen at `seconds` is a duplicate, same as `is_duplicate`
+    /// without recording it.
+    pub fn contains(&self, id: &str, seconds: i64) -> bool {
+        let hash = hash_id(id);
+        match *self {
+            Dedup::Set { window, ref seen, .. } => {
+                seen.get(&hash).is_some_and(|t| (seconds - t).abs() <= window)
+            }
+            Dedup::Bloom { window, start, ref current, ref previous } => {
+                let bits = bloom_bits(hash);
+                // the current filter would become the previous one
+                let rotate = start.is_none_or(|s| (seconds - s).abs() >= window);
+                bloom_contains(current, &bits) || (!rotate && bloom_contains(previous, &bits))
+            }
+        }
+    }
+
+    /// is_duplicate records the id seen at `seconds`, returns true if it was already seen within
+    /// the window.
+    pub fn is_duplicate(&mut self, id: &str, seconds: i64) -> bool {
+        let hash = hash_id(id);
+        match *self {
+            Dedup::Set { window, ref mut seen, ref mut order } => {
+                while order.front().is_some_and(|f| (seconds - f.0).abs() > window) {
+                    let (time, hash) = order.pop_front().unwrap();
+                    if seen.get(&hash) == Some(&time) {
+                        seen.remove(&hash);
+                    }
+                }
+                let duplicate = seen.get(&hash).is_some_and(|t| (seconds - t).abs() <= window);
+                seen.insert(hash, seconds);
+                order.push_back((seconds, hash));
+                duplicate
+            }
+            Dedup::Bloom { window, ref mut start, ref mut current, ref mut previous } => {
+                if start.is_none_or(|s| (seconds - s).abs() >= window) {
+                    *start = Some(seconds);
+                    ::std::mem::swap(current, previous);
+                    current.iter_mut().for_each(|w| *w = 0);
+                }
+                let bits = bloom_bits(hash);
+                let duplicate = bloom_contains(current, &bits) || bloom_contains(previous, &bits);
+                for b in bits {
+                    current[b / 64] |= 1 << (b % 64);
+                }
+                duplicate
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_dedup_set() {
+        let mut dedup = Dedup::new(600);
+        assert!(!dedup.is_duplicate("1", 0));
+        assert!(!dedup.is_duplicate("2", 60));
+        assert!(dedup.is_duplicate("1", 300));
+        // within the window of the last time seen
+        assert!(dedup.is_duplicate("1", 800));
+        assert!(!dedup.is_duplicate("2", 800));
+        assert!(!dedup.is_duplicate("1", 1500));
+        if let Dedup::Set { ref seen, .. } = dedup {
+            assert_eq!(seen.len(), 1);
+        }
+        // an older file is read after a newer one
+        assert!(!dedup.is_duplicate("1", 0));
+    }
+
+    #[test]
+    fn can_dedup_bloom() {
+        let mut dedup = Dedup::new(24 * 3600);
+        for id in 0..1000 {
+            assert!(!dedup.is_duplicate(&id.to_string(), id));
+        }
+        assert!(dedup.is_duplicate("10", 3600));
+        // still in the previous window
+        assert!(dedup.is_duplicate("20", 25 * 3600));
+        assert!(!dedup.is_duplicate("30", 50 * 3600));
+    }
+}
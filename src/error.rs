@@ -6,26 +6,45 @@ pub enum LogError {
     MisMatch,
     InvalidTimeFormat,
     MissingWaterMark,
+    InvalidDuration,
+    InvalidFormat,
+    InvalidFieldFormat(String),
+    InvalidRotation,
+    MissingConfig,
+    InvalidPipeline,
+    InvalidListen,
+    InvalidRoute(String),
+    InvalidCaptures(String),
+    InvalidFilter(String),
+    InvalidConfig(String),
 }
 
 pub type Result<T> = ::std::result::Result<T, LogError>;
 
 impl fmt::Display for LogError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(self.description())
-    }
-}
-
-impl Error for LogError {
-    fn description(&self) -> &str {
-        match *self {
+        let message = match *self {
             LogError::MisMatch => "MisMatch",
             LogError::InvalidTimeFormat => "Invalid Time Format",
             LogError::MissingWaterMark => "Not Available",
-        }
-    }
-
-    fn cause(&self) -> Option<&Error> {
-        None
+            LogError::InvalidDuration => "Invalid Duration",
+            LogError::InvalidFormat => "Invalid Output Format",
+            LogError::InvalidFieldFormat(ref format) => {
+                return write!(f, "Invalid Field Format: {}", format)
+            }
+            LogError::InvalidRotation => "Invalid Rotation Scheme",
+            LogError::MissingConfig => "Missing Config",
+            LogError::InvalidPipeline => "Invalid Pipeline",
+            LogError::InvalidListen => "Invalid Listen Address",
+            LogError::InvalidRoute(ref route) => return write!(f, "Invalid Route: {}", route),
+            LogError::InvalidCaptures(ref reason) => {
+                return write!(f, "Invalid Captures: {}", reason)
+            }
+            LogError::InvalidFilter(ref reason) => return write!(f, "Invalid Filter: {}", reason),
+            LogError::InvalidConfig(ref reason) => return write!(f, "Invalid Config: {}", reason),
+        };
+        f.write_str(message)
     }
 }
+
+impl Error for LogError {}
@@ -0,0 +1,153 @@
+use std::fmt;
+use std::path::Path;
+
+use logstat::LogTime;
+
+/// `Stage` is where a line ended up in the parser.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    /// counted by the result
+    Accepted,
+    /// neither the line matcher nor the batch matcher matched
+    NoMatch,
+    /// matched, but the time stamp at the beginning of the line can't be parsed
+    BadTimestamp,
+    /// matched, but the count/total capture is not a number
+    BadCount,
+    /// matched, but the id was already seen within the dedup window
+    Duplicate,
+}
+
+const STAGES: [Stage; 5] =
+    [Stage::Accepted, Stage::NoMatch, Stage::BadTimestamp, Stage::BadCount, Stage::Duplicate];
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Stage::Accepted => "accepted",
+            Stage::NoMatch => "no match",
+            Stage::BadTimestamp => "bad timestamp",
+            Stage::BadCount => "bad count",
+            Stage::Duplicate => "duplicate",
+        })
+    }
+}
+
+/// `Explanation` is how a single line went through `LogParser::explain_line`.
+pub struct Explanation<'a> {
+    /// "line" or "batch", None if no matcher fired
+    pub matcher: Option<&'static str>,
+    /// the named captures, except the time
+    pub captures: Vec<(&'static str, &'a str)>,
+    /// the time stamp text of the line
+    pub timestamp: &'a str,
+    pub time: Option<LogTime>,
+    /// the hour/batch the line is counted into
+    pub bucket: Option<usize>,
+    pub stage: Stage,
+}
+
+/// `Explain` collects the explanation of a monitor: the files it reads, a sample of lines per
+/// stage and how many lines ended up in every stage.
+pub struct Explain {
+    name: String,
+    /// how many lines to show per stage
+    samples: usize,
+    counters: [usize; 5],
+    pub lines: Vec<String>,
+}
+
+impl Explain {
+    pub fn new(name: &str, samples: usize) -> Explain {
+        Explain {
+            name: name.to_owned(),
+            samples,
+            counters: [0; 5],
+            lines: vec![format!("{}:", name)],
+        }
+    }
+
+    pub fn note(&mut self, note: String) {
+        self.lines.push(format!("  {}", note));
+    }
+
+    /// adds a selected file, `order` is the position the file is read in
+    pub fn file(&mut self, order: usize, path: &Path, size: u64) {
+        self.note(format!("file {}: {} ({} bytes)", order, path.display(), size));
+    }
+
+    /// counts the line, and shows it if the stage doesn't have enough samples yet.
+    /// `number` is the line number in its file.
+    pub fn record(&mut self, number: usize, line: &str, explanation: &Explanation) {
+        let stage = STAGES.iter().position(|s| *s == explanation.stage).unwrap();
+        self.counters[stage] += 1;
+        if self.counters[stage] > self.samples {
+            return;
+        }
+        let captures: Vec<_> = explanation.captures
+                                          .iter()
+                                          .map(|&(name, value)| format!("{}={:?}", name, value))
+                                          .collect();
+        self.lines.push(format!("  line {} [{}] matcher={} captures=[{}] timestamp={:?} time={} \
+                                 bucket={}",
+                                number,
+                                explanation.stage,
+                                explanation.matcher.unwrap_or("-"),
+                                captures.join(", "),
+                                explanation.timestamp,
+                                explanation.time.as_ref().map_or("-".to_owned(), |t| t.to_string()),
+                                explanation.bucket.map_or("-".to_owned(), |b| b.to_string())));
+        self.lines.push(format!("    > {}", line));
+    }
+
+    /// adds the counters of every stage
+    pub fn summary(&mut self) {
+        let total: usize = self.counters.iter().sum();
+        let counters: Vec<_> = STAGES.iter()
+                                     .zip(self.counters.iter())
+                                     .map(|(s, c)| format!("{} {}", c, s))
+                                     .collect();
+        let summary = format!("{}: {} lines, {}", self.name, total, counters.join(", "));
+        self.lines.push(summary);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn explanation(stage: Stage) -> Explanation<'static> {
+        Explanation {
+            matcher: Some("line"),
+            captures: vec![("watermark", "2015-11-09 01:58:00")],
+            timestamp: "2015-11-09 01:59:03",
+            time: "2015-11-09 01:59:03".parse().ok(),
+            bucket: Some(2015110901),
+            stage,
+        }
+    }
+
+    #[test]
+    fn can_sample_and_count_stages() {
+        let mut explain = Explain::new("Hour", 1);
+        explain.file(1, Path::new("hour.log"), 10);
+        explain.record(1, "first", &explanation(Stage::Accepted));
+        explain.record(2, "second", &explanation(Stage::Accepted));
+        explain.record(3, "third", &explanation(Stage::BadCount));
+        explain.summary();
+
+        assert_eq!(explain.lines,
+                   ["Hour:",
+                    "  file 1: hour.log (10 bytes)",
+                    "  line 1 [accepted] matcher=line \
+                     captures=[watermark=\"2015-11-09 01:58:00\"] timestamp=\"2015-11-09 01:59:03\" \
+                     time=2015-11-09 01:59:03 bucket=2015110901",
+                    "    > first",
+                    "  line 3 [bad count] matcher=line \
+                     captures=[watermark=\"2015-11-09 01:58:00\"] timestamp=\"2015-11-09 01:59:03\" \
+                     time=2015-11-09 01:59:03 bucket=2015110901",
+                    "    > third",
+                    "Hour: 3 lines, 2 accepted, 0 no match, 0 bad timestamp, 1 bad count, \
+                     0 duplicate"]);
+    }
+}
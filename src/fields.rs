@@ -0,0 +1,280 @@
+use std::fmt;
+
+use error::*;
+use logparser::LineInfo;
+
+/// `FieldFormat` is how the fields are written in a structured log line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldFormat {
+    /// the first JSON object of the line that parses, for example after the time stamp
+    Json,
+    /// space separated key=value pairs, values with spaces are quoted
+    Logfmt,
+}
+
+impl ::std::str::FromStr for FieldFormat {
+    type Err = LogError;
+
+    fn from_str(s: &str) -> Result<FieldFormat> {
+        match s {
+            "json" => Ok(FieldFormat::Json),
+            "logfmt" => Ok(FieldFormat::Logfmt),
+            _ => Err(LogError::InvalidFieldFormat(s.to_owned())),
+        }
+    }
+}
+
+/// the index of the closing quote of the string starting at `start`, skipping escaped quotes
+fn string_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return Some(i),
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// the index after the nested object/array starting at `start`
+fn nested_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => i = string_end(bytes, i)?,
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+fn skip_spaces(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() && (bytes[i] as char).is_whitespace() {
+        i += 1;
+    }
+    i
+}
+
+/// parse_json returns the top level fields of the first JSON object in the line, None if there
+/// is no valid object. A brace that doesn't start an object, as in `INFO {key=value} {...}`, is
+/// skipped. The values are raw: escapes like `\"` or `\n` are kept as written in the line, and
+/// nested objects and arrays are kept as JSON.
+pub fn parse_json(line: &str) -> Option<Vec<(&str, &str)>> {
+    line.match_indices('{').filter_map(|(start, _)| parse_object(line, start)).next()
+}
+
+/// the top level fields of the object starting at `start`
+fn parse_object(line: &str, start: usize) -> Option<Vec<(&str, &str)>> {
+    let bytes = line.as_bytes();
+    let mut i = skip_spaces(bytes, start + 1);
+    let mut fields = Vec::new();
+    if bytes.get(i) == Some(&b'}') {
+        return Some(fields);
+    }
+    loop {
+        if bytes.get(i) != Some(&b'"') {
+            return None;
+        }
+        let end = string_end(bytes, i)?;
+        let key = &line[i + 1..end];
+        i = skip_spaces(bytes, end + 1);
+        if bytes.get(i) != Some(&b':') {
+            return None;
+        }
+        i = skip_spaces(bytes, i + 1);
+        let value = match *bytes.get(i)? {
+            b'"' => {
+                let end = string_end(bytes, i)?;
+                let value = &line[i + 1..end];
+                i = end + 1;
+                value
+            }
+            b'{' | b'[' => {
+                let end = nested_end(bytes, i)?;
+                let value = &line[i..end];
+                i = end;
+                value
+            }
+            _ => {
+                let end = line[i..].find([',', '}']).map_or(line.len(), |e| i + e);
+                let value = line[i..end].trim_end();
+                i = end;
+                value
+            }
+        };
+        fields.push((key, value));
+        i = skip_spaces(bytes, i);
+        match bytes.get(i) {
+            Some(&b',') => i = skip_spaces(bytes, i + 1),
+            Some(&b'}') => return Some(fields),
+            _ => return None,
+        }
+    }
+}
+
+/// parse_logfmt returns the key=value pairs of the line, the values are unquoted but not
+/// unescaped. A key without value, or a word of the message, has an empty value.
+pub fn parse_logfmt(line: &str) -> Vec<(&str, &str)> {
+    let bytes = line.as_bytes();
+    let mut fields = Vec::new();
+    let mut i = skip_spaces(bytes, 0);
+    while i < bytes.len() {
+        let start = i;
+        while i < bytes.len() && bytes[i] != b'=' && !(bytes[i] as char).is_whitespace() {
+            i += 1;
+        }
+        let key = &line[start..i];
+        let mut value = "";
+        if bytes.get(i) == Some(&b'=') {
+            i += 1;
+            if bytes.get(i) == Some(&b'"') {
+                let end = string_end(bytes, i).unwrap_or(bytes.len());
+                value = &line[i + 1..end];
+                i = end + 1;
+            } else {
+                let start = i;
+                while i < bytes.len() && !(bytes[i] as char).is_whitespace() {
+                    i += 1;
+                }
+                value = &line[start..i];
+            }
+        }
+        fields.push((key, value));
+        i = skip_spaces(bytes, i);
+    }
+    fields
+}
+
+/// `FieldMatcher` matches structured lines by their field values, and takes the time stamp,
+/// watermark, count, batch id and total from named fields, see `LineInfo`.
+#[derive(Debug, Clone)]
+pub struct FieldMatcher {
+    format: FieldFormat,
+    /// field == value, all of them must hold
+    select: Vec<(String, String)>,
+    /// capture name -> field name
+    captures: Vec<(String, String)>,
+}
+
+impl FieldMatcher {
+    pub fn new(format: FieldFormat) -> FieldMatcher {
+        FieldMatcher {
+            format,
+            select: Vec::new(),
+            captures: Vec::new(),
+        }
+    }
+
+    /// only the lines where the field has the value
+    pub fn select(mut self, field: &str, value: &str) -> FieldMatcher {
+        self.select.push((field.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// takes the capture, for example "watermark", from the field
+    pub fn capture(mut self, name: &str, field: &str) -> FieldMatcher {
+        self.captures.push((name.to_owned(), field.to_owned()));
+        self
+    }
+
+    pub fn capture_names(&self) -> Vec<&str> {
+        self.captures.iter().map(|c| c.0.as_str()).collect()
+    }
+
+    pub fn match_line<'a>(&self, line: &'a str) -> Option<LineInfo<'a>> {
+        let fields = match self.format {
+            FieldFormat::Json => parse_json(line)?,
+            FieldFormat::Logfmt => parse_logfmt(line),
+        };
+        let field = |name: &str| fields.iter().find(|f| f.0 == name).map(|f| f.1);
+        if !self.select.iter().all(|(name, value)| field(name) == Some(value.as_str())) {
+            return None;
+        }
+        let mut info = LineInfo::default();
+        for (name, field_name) in &self.captures {
+            if let Some(value) = field(field_name) {
+                info.set(name, value);
+            }
+        }
+        Some(info)
+    }
+}
+
+impl fmt::Display for FieldMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.format)?;
+        for (name, value) in &self.select {
+            write!(f, " {}=={:?}", name, value)?;
+        }
+        for (name, field) in &self.captures {
+            write!(f, " {}<-{}", name, field)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_json() {
+        let line = r#"2015-11-09 02:00:00,000 INFO {"event": "committed", "count":3, "ok":true,
+                      "batch":{"id":"a,}"}, "msg":"say \"hi\"", "tags":[1, 2]}"#;
+        assert_eq!(parse_json(line).unwrap(),
+                   [("event", "committed"),
+                    ("count", "3"),
+                    ("ok", "true"),
+                    ("batch", r#"{"id":"a,}"}"#),
+                    ("msg", r#"say \"hi\""#),
+                    ("tags", "[1, 2]")]);
+        assert_eq!(parse_json("{}").unwrap().len(), 0);
+        assert_eq!(parse_json("no object"), None);
+        assert_eq!(parse_json(r#"{"broken": "#), None);
+        let line = r#"INFO {docWriteTime=2015-11-09} {"event":"committed","msg":"a\nb"}"#;
+        assert_eq!(parse_json(line).unwrap(), [("event", "committed"), ("msg", r"a\nb")]);
+    }
+
+    #[test]
+    fn can_parse_logfmt() {
+        let line = r#"ts=2015-11-09T02:00:00 level=info msg="batch done" count=3 dry"#;
+        assert_eq!(parse_logfmt(line),
+                   [("ts", "2015-11-09T02:00:00"),
+                    ("level", "info"),
+                    ("msg", "batch done"),
+                    ("count", "3"),
+                    ("dry", "")]);
+    }
+
+    #[test]
+    fn can_match_fields() {
+        let matcher = FieldMatcher::new(FieldFormat::Json)
+                          .select("event", "committed")
+                          .capture("watermark", "docWriteTime")
+                          .capture("count", "size");
+        let line = r#"x {"event":"committed","size":4,"docWriteTime":"2015-11-09 01:58:00"}"#;
+        let info = matcher.match_line(line).unwrap();
+        assert_eq!(info.watermark, Some("2015-11-09 01:58:00"));
+        assert_eq!(info.count, Some("4"));
+        assert!(matcher.match_line(r#"{"event":"started","size":4}"#).is_none());
+
+        let matcher = FieldMatcher::new(FieldFormat::Logfmt)
+                          .select("event", "batch")
+                          .capture("time", "ts")
+                          .capture("total", "size");
+        let info = matcher.match_line(r#"ts="2015-11-09 02:00:00" event=batch size=3"#).unwrap();
+        assert_eq!(info.time, Some("2015-11-09 02:00:00"));
+        assert_eq!(info.total, Some("3"));
+        assert_eq!(matcher.to_string(), r#"Logfmt event=="batch" time<-ts total<-size"#);
+    }
+}
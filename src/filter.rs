@@ -0,0 +1,430 @@
+use aho_corasick::{AcAutomaton, Automaton};
+use regex::Regex;
+use std::cmp::Ordering;
+use std::fmt;
+
+use error::*;
+use fields::{parse_json, parse_logfmt};
+
+/// log levels in order of severity, WARNING is read as WARN
+const LEVELS: [&str; 6] = ["TRACE", "DEBUG", "INFO", "WARN", "ERROR", "FATAL"];
+
+fn level(word: &str) -> Option<usize> {
+    let word = word.trim_matches(|c: char| !c.is_alphabetic()).to_uppercase();
+    let word = if word == "WARNING" { "WARN" } else { &word };
+    LEVELS.iter().position(|l| *l == word)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn test(self, ordering: Ordering) -> bool {
+        match self {
+            Op::Eq => ordering == Ordering::Equal,
+            Op::Ne => ordering != Ordering::Equal,
+            Op::Lt => ordering == Ordering::Less,
+            Op::Le => ordering != Ordering::Greater,
+            Op::Gt => ordering == Ordering::Greater,
+            Op::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    /// the line contains the literal, index of the literal in the automaton
+    Contains(usize),
+    Regex(Regex),
+    /// the first word of the line which is a log level, compared with the level
+    Level(Op, usize),
+    /// a JSON or logfmt field compared with the value, only == and !=
+    Field(String, Op, String),
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+}
+
+/// what is known about a line while evaluating the filter
+struct Line<'a> {
+    text: &'a str,
+    /// which literals the line contains
+    literals: Vec<bool>,
+    fields: Option<Vec<(&'a str, &'a str)>>,
+}
+
+impl<'a> Line<'a> {
+    fn field(&mut self, name: &str) -> Option<&'a str> {
+        let text = self.text;
+        let fields = self.fields.get_or_insert_with(|| {
+            parse_json(text).unwrap_or_else(|| parse_logfmt(text))
+        });
+        fields.iter().find(|f| f.0 == name).map(|f| f.1)
+    }
+}
+
+impl Expr {
+    fn eval(&self, line: &mut Line) -> bool {
+        match *self {
+            Expr::Contains(i) => line.literals[i],
+            Expr::Regex(ref r) => r.is_match(line.text),
+            Expr::Level(op, l) => {
+                line.text.split_whitespace().filter_map(level).next().is_some_and(|found| {
+                    op.test(found.cmp(&l))
+                })
+            }
+            Expr::Field(ref name, op, ref value) => {
+                op.test(if line.field(name) == Some(value.as_str()) {
+                    Ordering::Equal
+                } else {
+                    Ordering::Less
+                })
+            }
+            Expr::Not(ref e) => !e.eval(line),
+            Expr::And(ref es) => es.iter().all(|e| e.eval(line)),
+            Expr::Or(ref es) => es.iter().any(|e| e.eval(line)),
+        }
+    }
+
+    /// the literals of which at least one must be in a matching line, None if the expression
+    /// can match without any literal.
+    fn required(&self) -> Option<Vec<usize>> {
+        match *self {
+            Expr::Contains(i) => Some(vec![i]),
+            Expr::And(ref es) => es.iter().filter_map(|e| e.required()).min_by_key(|r| r.len()),
+            Expr::Or(ref es) => {
+                let mut required = Vec::new();
+                for e in es {
+                    required.extend(e.required()?);
+                }
+                Some(required)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Op(Op),
+    Str(String),
+    Regex(String),
+    Word(String),
+}
+
+fn invalid(reason: String) -> LogError {
+    LogError::InvalidFilter(reason)
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            ' ' | '\t' => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '&' if chars.peek() == Some(&'&') => {
+                chars.next();
+                Token::And
+            }
+            '|' if chars.peek() == Some(&'|') => {
+                chars.next();
+                Token::Or
+            }
+            '=' | '!' | '<' | '>' => {
+                let eq = chars.peek() == Some(&'=');
+                if eq {
+                    chars.next();
+                }
+                match (c, eq) {
+                    ('=', true) => Token::Op(Op::Eq),
+                    ('!', true) => Token::Op(Op::Ne),
+                    ('!', false) => Token::Not,
+                    ('<', false) => Token::Op(Op::Lt),
+                    ('<', true) => Token::Op(Op::Le),
+                    ('>', false) => Token::Op(Op::Gt),
+                    ('>', true) => Token::Op(Op::Ge),
+                    _ => return Err(invalid("single = , use ==".to_owned())),
+                }
+            }
+            '"' | '/' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') if c == '"' => text.extend(chars.next()),
+                        Some('\\') if chars.peek() == Some(&'/') => text.extend(chars.next()),
+                        Some(end) if end == c => break,
+                        Some(other) => text.push(other),
+                        None => return Err(invalid(format!("missing closing {}", c))),
+                    }
+                }
+                if c == '"' {
+                    Token::Str(text)
+                } else {
+                    Token::Regex(text)
+                }
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '.' || c == '-' => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_' || c == '.' || c == '-') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                match word.to_lowercase().as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Word(word),
+                }
+            }
+            c => return Err(invalid(format!("unexpected {}", c))),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// recursive descent parser of the filter language, it collects the literals on the way
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    literals: Vec<String>,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut es = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            es.push(self.and()?);
+        }
+        Ok(if es.len() == 1 { es.pop().unwrap() } else { Expr::Or(es) })
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut es = vec![self.not()?];
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            es.push(self.not()?);
+        }
+        Ok(if es.len() == 1 { es.pop().unwrap() } else { Expr::And(es) })
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.atom()
+    }
+
+    fn literal(&mut self, text: String) -> Expr {
+        let index = self.literals.iter().position(|l| *l == text).unwrap_or_else(|| {
+            self.literals.push(text);
+            self.literals.len() - 1
+        });
+        Expr::Contains(index)
+    }
+
+    fn regex(text: &str) -> Result<Expr> {
+        Regex::new(text).map(Expr::Regex).map_err(|e| invalid(format!("regex /{}/: {}", text, e)))
+    }
+
+    fn value(&mut self) -> Result<String> {
+        match self.next() {
+            Some(Token::Str(s)) | Some(Token::Word(s)) => Ok(s),
+            t => Err(invalid(format!("expects a value, found {:?}", t))),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Open) => {
+                let e = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(e),
+                    t => Err(invalid(format!("expects ), found {:?}", t))),
+                }
+            }
+            Some(Token::Str(s)) => Ok(self.literal(s)),
+            Some(Token::Regex(r)) => Parser::regex(&r),
+            Some(Token::Word(ref w)) if w == "contains" => {
+                let value = self.value()?;
+                Ok(self.literal(value))
+            }
+            Some(Token::Word(ref w)) if w == "matches" => Parser::regex(&self.value()?),
+            Some(Token::Word(name)) => {
+                let op = match self.next() {
+                    Some(Token::Op(op)) => op,
+                    t => {
+                        return Err(invalid(format!("expects an operator after {}, found {:?}",
+                                                   name,
+                                                   t)))
+                    }
+                };
+                let value = self.value()?;
+                if name == "level" {
+                    level(&value).map(|l| Expr::Level(op, l))
+                                 .ok_or_else(|| invalid(format!("unknown level {}", value)))
+                } else if op == Op::Eq || op == Op::Ne {
+                    Ok(Expr::Field(name, op, value))
+                } else {
+                    Err(invalid(format!("field {} only supports == and !=", name)))
+                }
+            }
+            t => Err(invalid(format!("unexpected {:?}", t))),
+        }
+    }
+}
+
+/// `Filter` selects lines by a boolean expression, for example
+/// `"committed" AND NOT "dry-run"`, `/took \d+ms/ OR level >= WARN`, `event == "batch"`.
+///
+/// * `"text"` or `contains "text"`: the line contains the text
+/// * `/regex/` or `matches "regex"`: the regex matches the line
+/// * `level OP LEVEL`: the first log level word of the line, OP is one of == != < <= > >=
+/// * `field == "value"`, `field != "value"`: a JSON or logfmt field of the line
+/// * `AND`, `OR`, `NOT` (or `&&`, `||`, `!`) and parentheses
+///
+/// All the literals are searched in a single pass with Aho-Corasick, and a line without any of
+/// the required literals is rejected before the regexes and fields are evaluated.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    source: String,
+    expr: Expr,
+    literals: Option<AcAutomaton<String>>,
+    count: usize,
+    required: Option<Vec<usize>>,
+}
+
+impl ::std::str::FromStr for Filter {
+    type Err = LogError;
+
+    fn from_str(s: &str) -> Result<Filter> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+            literals: Vec::new(),
+        };
+        let expr = parser.or()?;
+        if let Some(t) = parser.peek() {
+            return Err(invalid(format!("unexpected {:?}", t)));
+        }
+        Ok(Filter {
+            source: s.to_owned(),
+            required: expr.required(),
+            expr,
+            count: parser.literals.len(),
+            literals: if parser.literals.is_empty() {
+                None
+            } else {
+                Some(AcAutomaton::new(parser.literals))
+            },
+        })
+    }
+}
+
+impl Filter {
+    /// and selects the lines selected by both filters
+    pub fn and(&self, other: &Filter) -> Filter {
+        format!("({}) AND ({})", self.source, other.source).parse().expect("Valid filters")
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        let mut line = Line {
+            text,
+            literals: vec![false; self.count],
+            fields: None,
+        };
+        if let Some(ref literals) = self.literals {
+            for m in literals.find_overlapping(text) {
+                line.literals[m.pati] = true;
+            }
+        }
+        if let Some(ref required) = self.required {
+            if !required.iter().any(|i| line.literals[*i]) {
+                return false;
+            }
+        }
+        self.expr.eval(&mut line)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(source: &str) -> Filter {
+        source.parse().unwrap()
+    }
+
+    #[test]
+    fn can_combine_literals() {
+        let f = filter(r#""committed" AND NOT "dry-run""#);
+        assert!(f.is_match("2015-11-09 02:00:00,000 INFO committed"));
+        assert!(!f.is_match("2015-11-09 02:00:00,000 INFO committed dry-run"));
+        assert!(!f.is_match("2015-11-09 02:00:00,000 INFO started"));
+        assert_eq!(f.required, Some(vec![0]));
+
+        let f = filter(r#"("a" || contains "b") && !("c")"#);
+        assert!(f.is_match("xbx"));
+        assert!(!f.is_match("abc"));
+        assert_eq!(f.required, Some(vec![0, 1]));
+    }
+
+    #[test]
+    fn can_filter_regex_level_and_fields() {
+        let f = filter(r#"/took \d+ms/ OR level >= warn"#);
+        assert!(f.is_match("2015-11-09 02:00:00,000 INFO took 15ms"));
+        assert!(f.is_match("2015-11-09 02:00:00,000 [ERROR] failed"));
+        assert!(f.is_match("2015-11-09 02:00:00,000 WARNING slow"));
+        assert!(!f.is_match("2015-11-09 02:00:00,000 INFO took long"));
+        assert_eq!(f.required, None);
+
+        let f = filter(r#"event == "committed" and dry != true"#);
+        assert!(f.is_match(r#"2015-11-09 02:00:00,000 {"event":"committed"}"#));
+        assert!(f.is_match("ts=1 event=committed dry=false"));
+        assert!(!f.is_match("ts=1 event=committed dry=true"));
+        assert!(!f.is_match("ts=1 event=started"));
+    }
+
+    #[test]
+    fn can_reject_invalid_filters() {
+        for source in &[r#""a" AND"#, r#"("a""#, r#"level > LOUD"#, r#"/(/"#, r#"a = "b""#,
+                        r#"a > "b""#, r#""a" "b""#, r#""a"#] {
+            assert!(source.parse::<Filter>().is_err(), "{}", source);
+        }
+    }
+}
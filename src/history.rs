@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::sync::Arc;
+use logstat::{hour_minutes, minutes_to_hour};
+
+/// minutes of a week, the baseline compares the same hour of the same weekday
+const WEEK: i64 = 7 * 24 * 60;
+
+/// `Sample` is what we keep in the history for one hour of a monitor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub done: u32,
+    /// p90 delay of the hour in seconds, None if the lines don't carry a watermark
+    pub delay: Option<i64>,
+}
+
+/// `History` is the hourly results of previous runs, saved as a tab separated file:
+/// "name, hour, done, delay" per line, delay is "-" if not available.
+/// A newer run overrides the hours it has seen again.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    samples: BTreeMap<(String, usize), Sample>,
+}
+
+impl History {
+    /// load reads the history file, a missing file is an empty history.
+    pub fn load(path: &str) -> io::Result<History> {
+        let mut history = History::default();
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(history),
+            Err(e) => return Err(e),
+        };
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let fields: Vec<_> = line.split('\t').collect();
+            if let [name, hour, done, delay] = fields[..] {
+                if let (Ok(hour), Ok(done)) = (hour.parse(), done.parse()) {
+                    history.insert(name,
+                                   hour,
+                                   Sample {
+                                       done,
+                                       delay: delay.parse().ok(),
+                                   });
+                }
+            }
+        }
+        Ok(history)
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        for (&(ref name, hour), sample) in &self.samples {
+            writeln!(file,
+                     "{}\t{}\t{}\t{}",
+                     name,
+                     hour,
+                     sample.done,
+                     sample.delay.map_or("-".to_owned(), |d| d.to_string()))?;
+        }
+        Ok(())
+    }
+
+    pub fn insert(&mut self, name: &str, hour: usize, sample: Sample) {
+        self.samples.insert((name.to_owned(), hour), sample);
+    }
+
+    /// Returns the samples of the same hour of the same weekday over the last `weeks` weeks.
+    pub fn same_hour(&self, name: &str, hour: usize, weeks: usize) -> Vec<&Sample> {
+        let now = match hour_minutes(hour) {
+            Some(m) => m,
+            None => return Vec::new(),
+        };
+        (1..weeks as i64 + 1)
+            .filter_map(|w| minutes_to_hour(now - w * WEEK))
+            .filter_map(|h| self.samples.get(&(name.to_owned(), h)))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    /// mean +/- threshold * standard deviation
+    Sigma,
+    /// median +/- threshold * median absolute deviation, not affected by a single bad week
+    Mad,
+}
+
+/// `AnomalyOptions` are the settings to compare a hour with its baseline.
+#[derive(Debug, Clone)]
+pub struct AnomalyOptions {
+    pub history: Arc<History>,
+    pub weeks: usize,
+    /// how many standard deviations/MADs away from the baseline is an anomaly
+    pub threshold: f64,
+    pub method: Method,
+}
+
+/// `Expected` is the range of values the baseline expects.
+#[derive(Debug, PartialEq)]
+pub struct Expected {
+    pub low: f64,
+    pub high: f64,
+}
+
+impl Expected {
+    pub fn is_anomaly(&self, value: f64) -> bool {
+        value < self.low || value > self.high
+    }
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let n = values.len();
+    if n.is_multiple_of(2) {
+        (values[n / 2 - 1] + values[n / 2]) / 2.0
+    } else {
+        values[n / 2]
+    }
+}
+
+impl AnomalyOptions {
+    /// Returns the expected range from the baseline values, None if there are less than 2
+    /// values to compare with.
+    pub fn expected(&self, values: &[f64]) -> Option<Expected> {
+        if values.len() < 2 {
+            return None;
+        }
+        let (center, spread) = match self.method {
+            Method::Sigma => {
+                let n = values.len() as f64;
+                let mean = values.iter().sum::<f64>() / n;
+                let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+                (mean, variance.sqrt())
+            }
+            Method::Mad => {
+                let center = median(&mut values.to_vec());
+                let mut deviations: Vec<_> = values.iter().map(|v| (v - center).abs()).collect();
+                // scaled to be comparable with the standard deviation of a normal distribution
+                (center, 1.4826 * median(&mut deviations))
+            }
+        };
+        Some(Expected {
+            low: (center - self.threshold * spread).max(0.0),
+            high: center + self.threshold * spread,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(method: Method, history: History) -> AnomalyOptions {
+        AnomalyOptions {
+            history: Arc::new(history),
+            weeks: 4,
+            threshold: 3.0,
+            method,
+        }
+    }
+
+    #[test]
+    fn can_find_same_hour_of_previous_weeks() {
+        let mut history = History::default();
+        // Monday 2015-11-09 02, and the Mondays before
+        for hour in &[2015110202, 2015102602, 2015101902, 2015101202, 2015100502] {
+            history.insert("Hour", *hour, Sample { done: 100, delay: None });
+        }
+        history.insert("Hour", 2015110802, Sample { done: 1, delay: None });
+        history.insert("Batch", 2015110202, Sample { done: 1, delay: None });
+
+        // the 5th week is too old
+        assert_eq!(history.same_hour("Hour", 2015110902, 4).len(), 4);
+        assert!(history.same_hour("Hour", 2015110902, 4).iter().all(|s| s.done == 100));
+        assert_eq!(history.same_hour("Hour", 2015110903, 4).len(), 0);
+    }
+
+    #[test]
+    fn can_calculate_expected_range() {
+        let sigma = options(Method::Sigma, History::default());
+        let expected = sigma.expected(&[90.0, 100.0, 110.0]).unwrap();
+        assert_eq!(expected, Expected { low: 70.0, high: 130.0 });
+        assert!(expected.is_anomaly(20.0));
+        assert!(!expected.is_anomaly(120.0));
+        assert_eq!(sigma.expected(&[100.0]), None);
+
+        // a single bad week doesn't hide the anomaly with MAD
+        let mad = options(Method::Mad, History::default());
+        let expected = mad.expected(&[100.0, 100.0, 104.0, 0.0]).unwrap();
+        assert!(expected.is_anomaly(50.0));
+        let expected = sigma.expected(&[100.0, 100.0, 104.0, 0.0]).unwrap();
+        assert!(!expected.is_anomaly(50.0));
+    }
+
+    #[test]
+    fn can_save_and_load() {
+        let name = format!("tc_tool_history_test_{}.tsv", ::std::process::id());
+        let path = ::std::env::temp_dir().join(name);
+        let path = path.to_str().unwrap();
+        let mut history = History::default();
+        history.insert("Hour", 2015110902, Sample { done: 3, delay: Some(10) });
+        history.insert("Batch", 2015110902, Sample { done: 5, delay: None });
+        history.save(path).unwrap();
+
+        let loaded = History::load(path).unwrap();
+        assert_eq!(loaded.samples, history.samples);
+        assert_eq!(History::load("missing/history.tsv").unwrap().samples.len(), 0);
+    }
+}
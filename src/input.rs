@@ -0,0 +1,191 @@
+use glob::{glob, Pattern};
+use std::cmp::Reverse;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use logstat::LogTime;
+use rotation::Rotation;
+
+/// how many lines to look at for the first time stamp of a file
+pub const HEAD_LINES: usize = 20;
+
+/// `InputOptions` controls how directory and glob inputs are expanded into files.
+#[derive(Debug, Clone, Default)]
+pub struct InputOptions {
+    /// also list the files of the sub directories of a directory input
+    pub recursive: bool,
+    /// only the file names matching any of the patterns, all files if empty
+    pub include: Vec<Pattern>,
+    /// skip the file names matching any of the patterns
+    pub exclude: Vec<Pattern>,
+    /// naming scheme of the rotated files
+    pub rotation: Rotation,
+}
+
+impl InputOptions {
+    /// whether the file name matches the include/exclude patterns
+    pub fn accepts(&self, path: &Path) -> bool {
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(n) => n,
+            None => return false,
+        };
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(name))) &&
+        !self.exclude.iter().any(|p| p.matches(name))
+    }
+
+    fn walk(&self, dir: &Path, files: &mut Vec<PathBuf>) {
+        let entries = match fs::read_dir(dir) {
+            Ok(e) => e,
+            Err(_) => return,
+        };
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            if path.is_dir() {
+                if self.recursive {
+                    self.walk(&path, files);
+                }
+            } else if self.accepts(&path) {
+                files.push(path);
+            }
+        }
+    }
+}
+
+/// list_files expands the inputs into the log files, newest first. An input can be a file, a
+/// directory or a glob pattern, the include/exclude patterns don't apply to explicit files.
+pub fn list_files(inputs: &[String], options: &InputOptions) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_file() {
+            files.push(path.to_path_buf());
+        } else if path.is_dir() {
+            options.walk(path, &mut files);
+        } else if let Ok(paths) = glob(input) {
+            files.extend(paths.filter_map(|r| r.ok())
+                              .filter(|p| p.is_file() && options.accepts(p)));
+        }
+    }
+    files.sort();
+    files.dedup();
+    sorted_path(&files, &options.rotation)
+}
+
+/// first_timestamp returns the time stamp of the first line starting with one, in seconds.
+fn first_timestamp(path: &Path) -> Option<i64> {
+    let file = File::open(path).ok()?;
+    head_timestamp(BufReader::new(file).lines().map_while(|line| line.ok()))
+}
+
+/// head_timestamp returns the time stamp of the first of the head lines starting with one, in
+/// seconds.
+pub fn head_timestamp<I: Iterator<Item = String>>(lines: I) -> Option<i64> {
+    lines.take(HEAD_LINES)
+         .filter_map(|line| line.split(',').next().and_then(|t| t.parse::<LogTime>().ok()))
+         .map(|t| t.seconds())
+         .next()
+}
+
+/// sort the path newest first, base on the rotation scheme of the file names. Files with the
+/// same rotation key, or all files for `Rotation::Content`, are sorted by the time stamp of their
+/// first line, so files from several directories can be mixed. Files without time stamp go last,
+/// ordered by extension. If no extension then assume it as 0, for example
+/// tc.log
+/// tc.log.1
+/// tc.log.2
+/// ...
+/// tc.log.10
+pub fn sorted_path(paths: &[PathBuf], rotation: &Rotation) -> Vec<PathBuf> {
+    let firsts: Vec<_> = paths.iter().map(|name| first_timestamp(name)).collect();
+    sorted_by(paths, &firsts, rotation)
+}
+
+/// Same as `sorted_path`, with the time stamps of the first lines of the files already known.
+pub fn sorted_by(paths: &[PathBuf], firsts: &[Option<i64>], rotation: &Rotation) -> Vec<PathBuf> {
+    let mut paths_new: Vec<_> = paths.iter().zip(firsts.iter().cloned()).collect();
+    paths_new.sort_by_key(|a| Rotation::Numeric.key(a.0));
+    paths_new.sort_by_key(|a| Reverse(a.1));
+    paths_new.sort_by_key(|a| rotation.key(a.0));
+    paths_new.iter().map(|a| a.0).cloned().collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use std::io::Write;
+
+    /// creates the log files under a new temp directory, unique to the test run, returns the
+    /// directory. The files are (path, text), the text is written as a line.
+    pub fn create_logs(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for &(file, first_line) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            writeln!(File::create(path).unwrap(), "{}", first_line).unwrap();
+        }
+        dir
+    }
+
+    fn names(dir: &Path, files: &[PathBuf]) -> Vec<String> {
+        files.iter()
+             .map(|f| f.strip_prefix(dir).unwrap().to_str().unwrap().to_owned())
+             .collect()
+    }
+
+    #[test]
+    fn can_sort_by_extension() {
+        let dir = create_logs("tc_tool_sort_ext",
+                              &[("tc.log.10", ""), ("tc.log", ""), ("tc.log.2", ""), ("tc.log.1", "")]);
+        let files = list_files(&[dir.join("tc.log*").to_str().unwrap().to_owned()],
+                               &InputOptions::default());
+        assert_eq!(names(&dir, &files), ["tc.log", "tc.log.1", "tc.log.2", "tc.log.10"]);
+    }
+
+    #[test]
+    fn can_sort_by_content() {
+        let dir = create_logs("tc_tool_sort_content",
+                              &[("a/tc.log", "2015-11-09 03:00:00,001 INFO"),
+                                ("a/tc.log.1", "2015-11-09 01:00:00,001 INFO"),
+                                ("b/tc.log", "2015-11-09 02:00:00,001 INFO"),
+                                ("b/tc.log.1", "no time stamp"),
+                                ("b/sub/tc.log", "2015-11-09 04:00:00,001 INFO"),
+                                ("b/tc.gz", "2015-11-09 05:00:00,001 INFO")]);
+        let inputs = [dir.join("a").to_str().unwrap().to_owned(),
+                      dir.join("b").to_str().unwrap().to_owned()];
+
+        let options = InputOptions {
+            exclude: vec![Pattern::new("*.gz").unwrap()],
+            ..InputOptions::default()
+        };
+        let files = list_files(&inputs, &options);
+        assert_eq!(names(&dir, &files), ["a/tc.log", "b/tc.log", "a/tc.log.1", "b/tc.log.1"]);
+
+        let options = InputOptions {
+            recursive: true,
+            include: vec![Pattern::new("tc.log").unwrap()],
+            ..InputOptions::default()
+        };
+        let files = list_files(&inputs, &options);
+        assert_eq!(names(&dir, &files), ["b/sub/tc.log", "a/tc.log", "b/tc.log"]);
+
+        // the current files first, then by content
+        let options = InputOptions {
+            exclude: vec![Pattern::new("*.gz").unwrap()],
+            rotation: Rotation::Numeric,
+            ..InputOptions::default()
+        };
+        let files = list_files(&inputs, &options);
+        assert_eq!(names(&dir, &files), ["a/tc.log", "b/tc.log", "a/tc.log.1", "b/tc.log.1"]);
+        let files = list_files(&[dir.join("a").to_str().unwrap().to_owned(),
+                                 dir.join("b/tc.gz").to_str().unwrap().to_owned()],
+                               &options);
+        assert_eq!(names(&dir, &files), ["b/tc.gz", "a/tc.log", "a/tc.log.1"]);
+
+        // explicit files are always included
+        let files = list_files(&[dir.join("b/tc.gz").to_str().unwrap().to_owned()], &options);
+        assert_eq!(names(&dir, &files), ["b/tc.gz"]);
+    }
+}
@@ -1,26 +1,145 @@
 use std::default::Default;
-use std::hash::Hasher;
+use std::hash::{BuildHasherDefault, Hasher};
 
+/// Multiplier for the integer hash, 2^64 / golden ratio (Fibonacci hashing).
+const SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// `IntHasher` is a hasher for integer keys such as the `YYYYMMDDHH` hour index or the batch
+/// index. The key is multiplied by a large odd constant instead of being used as it is: the std
+/// `HashMap` picks the bucket from the low bits and probes with the top bits, and for keys like
+/// 2015110901 the top bits of an identity hash are always zero.
 #[allow(missing_copy_implementations)]
-struct IntHasher(u64);
+#[derive(Default)]
+pub struct IntHasher(u64);
 
-impl Default for IntHasher {
-    #[inline]
-    fn default() -> IntHasher {
-        IntHasher(0)
-    }
-}
+pub type IntBuildHasher = BuildHasherDefault<IntHasher>;
 
 impl Hasher for IntHasher {
     #[inline]
     fn finish(&self) -> u64 {
         self.0
     }
+
+    /// Fallback for non-integer keys, fold every byte into the state.
     #[inline]
     fn write(&mut self, bytes: &[u8]) {
-        for byte in buf.iter() {
-            self.0 += *byte as u64;
-            return;
+        for byte in bytes {
+            self.0 = (self.0.rotate_left(8) ^ u64::from(*byte)).wrapping_mul(SEED);
         }
     }
-}
\ No newline at end of file
+
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        self.0 = i.wrapping_mul(SEED);
+    }
+
+    #[inline]
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, HashMap};
+    use std::hash::{BuildHasherDefault, Hash};
+    use fnv::FnvHasher;
+    use test::{black_box, Bencher};
+
+    fn hash<T: Hash>(value: T) -> u64 {
+        let mut hasher = IntHasher::default();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// one week of hour keys, 2015-11-01 00 ... 2015-11-07 23
+    fn hour_keys() -> Vec<usize> {
+        (1..8).flat_map(|day| (0..24).map(move |hour| 2015110000 + day * 100 + hour)).collect()
+    }
+
+    /// trim_index of "2015-11-09 02:01:03" style batch start times
+    fn batch_keys() -> Vec<usize> {
+        (0..200).map(|n| 20151109000000 + n * 1523).collect()
+    }
+
+    #[test]
+    fn int_keys_are_distinct() {
+        let mut hashes: Vec<_> = hour_keys().into_iter().map(hash).collect();
+        hashes.sort();
+        hashes.dedup();
+        assert_eq!(hashes.len(), hour_keys().len());
+
+        // the top bits must vary as well, not only the low ones
+        let mut top: Vec<_> = hour_keys().into_iter().map(|k| hash(k) >> 57).collect();
+        top.sort();
+        top.dedup();
+        assert!(top.len() > 32);
+    }
+
+    #[test]
+    fn can_hash_bytes() {
+        assert_eq!(hash("abc"), hash("abc"));
+        assert!(hash("abc") != hash("acb"));
+    }
+
+    /// The access pattern of `HourResult`: every line looks up its hour, then the keys are
+    /// sorted once for printing.
+    fn hash_map_round<H: Hasher + Default>(keys: &[usize]) -> Vec<usize> {
+        let mut map: HashMap<usize, usize, BuildHasherDefault<H>> = HashMap::default();
+        for _ in 0..50 {
+            for key in keys {
+                *map.entry(*key).or_insert(0) += 1;
+            }
+        }
+        let mut sorted: Vec<_> = map.keys().cloned().collect();
+        sorted.sort();
+        sorted
+    }
+
+    fn btree_map_round(keys: &[usize]) -> Vec<usize> {
+        let mut map = BTreeMap::new();
+        for _ in 0..50 {
+            for key in keys {
+                *map.entry(*key).or_insert(0) += 1;
+            }
+        }
+        map.keys().cloned().collect()
+    }
+
+    #[bench]
+    fn bench_hour_keys_int_hasher(b: &mut Bencher) {
+        let keys = hour_keys();
+        b.iter(|| black_box(hash_map_round::<IntHasher>(&keys)));
+    }
+
+    #[bench]
+    fn bench_hour_keys_fnv(b: &mut Bencher) {
+        let keys = hour_keys();
+        b.iter(|| black_box(hash_map_round::<FnvHasher>(&keys)));
+    }
+
+    #[bench]
+    fn bench_hour_keys_btree(b: &mut Bencher) {
+        let keys = hour_keys();
+        b.iter(|| black_box(btree_map_round(&keys)));
+    }
+
+    #[bench]
+    fn bench_batch_keys_int_hasher(b: &mut Bencher) {
+        let keys = batch_keys();
+        b.iter(|| black_box(hash_map_round::<IntHasher>(&keys)));
+    }
+
+    #[bench]
+    fn bench_batch_keys_fnv(b: &mut Bencher) {
+        let keys = batch_keys();
+        b.iter(|| black_box(hash_map_round::<FnvHasher>(&keys)));
+    }
+
+    #[bench]
+    fn bench_batch_keys_btree(b: &mut Bencher) {
+        let keys = batch_keys();
+        b.iter(|| black_box(btree_map_round(&keys)));
+    }
+}
@@ -0,0 +1,332 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use error::*;
+use logstat::LogTime;
+
+/// Upper limit of an octet counted frame, a larger length is read as a plain line instead.
+const MAX_FRAME: usize = 1 << 20;
+
+/// Upper limit of the open TCP connections, every one has its own thread. More connections are
+/// closed at once.
+const MAX_CONNECTIONS: usize = 64;
+
+/// `Message` is a log line received by the listener.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    /// the APP-NAME of a syslog message, None for a plain line
+    pub app: Option<String>,
+    pub line: String,
+}
+
+impl Message {
+    /// parse reads a RFC 5424 syslog message, anything else is a plain line. The MSG of a syslog
+    /// message is the line, prefixed by the syslog TIMESTAMP if it doesn't start with a time stamp
+    /// itself, so the monitors can count it. The time zone of the TIMESTAMP is ignored.
+    pub fn parse(frame: &str) -> Message {
+        match parse_syslog(frame) {
+            Some((time, app, msg)) => {
+                let starts_with_time = msg.split(',').next().is_some_and(|t| {
+                    t.parse::<LogTime>().is_ok()
+                });
+                let line = match syslog_time(time) {
+                    Some(ref time) if !starts_with_time => format!("{} {}", time, msg),
+                    _ => msg.to_owned(),
+                };
+                Message {
+                    app: app.map(String::from),
+                    line,
+                }
+            }
+            None => {
+                Message {
+                    app: None,
+                    line: frame.to_owned(),
+                }
+            }
+        }
+    }
+}
+
+/// parse_syslog splits a RFC 5424 message into (TIMESTAMP, APP-NAME, MSG), the nil values "-"
+/// are None:
+/// <PRI>VERSION TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA [MSG]
+fn parse_syslog(frame: &str) -> Option<(Option<&str>, Option<&str>, &str)> {
+    let rest = frame.strip_prefix('<')?;
+    let end = rest.find('>')?;
+    let (pri, rest) = (&rest[..end], &rest[end + 1..]);
+    if pri.is_empty() || pri.len() > 3 || !pri.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let header: Vec<_> = rest.splitn(7, ' ').collect();
+    if header.len() < 7 || header[0].is_empty() || !header[0].bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let data = header[6];
+    let msg = if let Some(msg) = data.strip_prefix('-') {
+        msg
+    } else if data.starts_with('[') {
+        &data[structured_data_len(data)?..]
+    } else {
+        return None;
+    };
+    let msg = msg.strip_prefix(' ').unwrap_or(msg);
+    Some((nil(header[1]), nil(header[3]), msg.strip_prefix('\u{feff}').unwrap_or(msg)))
+}
+
+/// the nil value "-" of a syslog field is None
+fn nil(value: &str) -> Option<&str> {
+    if value == "-" { None } else { Some(value) }
+}
+
+/// the length of the SD-ELEMENTs at the start of the text, "]" is escaped in the quoted values
+fn structured_data_len(data: &str) -> Option<usize> {
+    let (mut quoted, mut escaped, mut open) = (false, false, false);
+    for (i, c) in data.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' if open => quoted = !quoted,
+            '[' if !open => open = true,
+            ']' if open && !quoted => open = false,
+            _ if !open => return Some(i),
+            _ => {}
+        }
+    }
+    if open { None } else { Some(data.len()) }
+}
+
+/// syslog_time converts a RFC 3339 TIMESTAMP, for example "2015-11-09T02:01:03.001Z", into the
+/// time stamp of the log lines: "2015-11-09 02:01:03,001"
+fn syslog_time(time: Option<&str>) -> Option<String> {
+    let time = time?;
+    if time.len() < 19 || !time.is_char_boundary(19) {
+        return None;
+    }
+    let seconds = time[..19].replacen('T', " ", 1);
+    seconds.parse::<LogTime>().ok()?;
+    let fraction: String = time[19..].strip_prefix('.')
+                                     .unwrap_or("")
+                                     .chars()
+                                     .take_while(|c| c.is_ascii_digit())
+                                     .chain("000".chars())
+                                     .take(3)
+                                     .collect();
+    Some(format!("{},{}", seconds, fraction))
+}
+
+/// read_frame reads the next frame of a stream into `frame`, returns false at the end of the
+/// stream. A frame is either octet counted, "LENGTH SP MESSAGE" (RFC 6587), or a line. The
+/// message of an octet counted frame starts with the "<PRI>" of syslog, so a line starting with a
+/// number, as "42 items committed", is read as a line.
+pub fn read_frame<R: BufRead>(reader: &mut R, frame: &mut Vec<u8>) -> io::Result<bool> {
+    frame.clear();
+    loop {
+        let byte = match reader.fill_buf()?.first() {
+            Some(b) => *b,
+            None => return Ok(!frame.is_empty()),
+        };
+        match byte {
+            b'0'..=b'9' if frame.len() < 7 => frame.push(byte),
+            b' ' if !frame.is_empty() && frame.iter().all(|b| b.is_ascii_digit()) => {
+                let length: usize = String::from_utf8_lossy(frame).parse().unwrap_or(0);
+                frame.push(byte);
+                reader.consume(1);
+                if length > MAX_FRAME || reader.fill_buf()?.first() != Some(&b'<') {
+                    break;
+                }
+                frame.clear();
+                reader.by_ref().take(length as u64).read_to_end(frame)?;
+                return Ok(true);
+            }
+            _ => break,
+        }
+        reader.consume(1);
+    }
+    reader.read_until(b'\n', frame)?;
+    while frame.last().is_some_and(|b| *b == b'\n' || *b == b'\r') {
+        frame.pop();
+    }
+    Ok(true)
+}
+
+/// `Listen` is where the listener accepts the log lines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Listen {
+    /// a stream of frames per connection, see `read_frame`
+    Tcp(SocketAddr),
+    /// a message per datagram, or a line for the plain lines
+    Udp(SocketAddr),
+}
+
+impl ::std::str::FromStr for Listen {
+    type Err = LogError;
+
+    /// "tcp://ADDRESS:PORT" or "udp://ADDRESS:PORT", for example "udp://0.0.0.0:514"
+    fn from_str(s: &str) -> Result<Listen> {
+        let address = |a: &str| a.parse().map_err(|_| LogError::InvalidListen);
+        if let Some(a) = s.strip_prefix("tcp://") {
+            Ok(Listen::Tcp(address(a)?))
+        } else if let Some(a) = s.strip_prefix("udp://") {
+            Ok(Listen::Udp(address(a)?))
+        } else {
+            Err(LogError::InvalidListen)
+        }
+    }
+}
+
+impl Listen {
+    /// start binds the address and sends the messages received into `sender` from background
+    /// threads, until the receiver is dropped. Returns the address bound, the port 0 is any free
+    /// port.
+    pub fn start(&self, sender: Sender<Message>) -> io::Result<SocketAddr> {
+        match *self {
+            Listen::Tcp(address) => {
+                let listener = TcpListener::bind(address)?;
+                let bound = listener.local_addr()?;
+                let open = Arc::new(AtomicUsize::new(0));
+                thread::spawn(move || {
+                    for stream in listener.incoming().filter_map(|s| s.ok()) {
+                        if open.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                            open.fetch_sub(1, Ordering::SeqCst);
+                            continue;
+                        }
+                        let sender = sender.clone();
+                        let open = open.clone();
+                        thread::spawn(move || {
+                            let mut reader = BufReader::new(stream);
+                            let mut frame = Vec::new();
+                            while let Ok(true) = read_frame(&mut reader, &mut frame) {
+                                let message = Message::parse(&String::from_utf8_lossy(&frame));
+                                if sender.send(message).is_err() {
+                                    break;
+                                }
+                            }
+                            open.fetch_sub(1, Ordering::SeqCst);
+                        });
+                    }
+                });
+                Ok(bound)
+            }
+            Listen::Udp(address) => {
+                let socket = UdpSocket::bind(address)?;
+                let bound = socket.local_addr()?;
+                thread::spawn(move || {
+                    let mut buffer = vec![0; 65536];
+                    while let Ok(size) = socket.recv(&mut buffer) {
+                        let datagram = String::from_utf8_lossy(&buffer[..size]);
+                        let frames: Vec<&str> = if datagram.starts_with('<') {
+                            vec![datagram.trim_end_matches(['\r', '\n'])]
+                        } else {
+                            datagram.lines().collect()
+                        };
+                        for frame in frames.into_iter().filter(|f| !f.is_empty()) {
+                            if sender.send(Message::parse(frame)).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                });
+                Ok(bound)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use std::net::TcpStream;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    #[test]
+    fn can_parse_syslog() {
+        let message = Message::parse("<165>1 2015-11-09T02:01:03.5Z host publisher 42 ID47 \
+                                      [meta a=\"x\\]\"][b] docWriteTime=2015-11-09 02:01:00}");
+        assert_eq!(message.app.as_deref(), Some("publisher"));
+        assert_eq!(message.line, "2015-11-09 02:01:03,500 docWriteTime=2015-11-09 02:01:00}");
+
+        // the time stamp of the message is kept
+        let message = Message::parse("<13>1 2015-11-09T02:01:03Z host - - - - \u{feff}\
+                                      2015-11-09 02:00:00,001 committed");
+        assert_eq!(message.app, None);
+        assert_eq!(message.line, "2015-11-09 02:00:00,001 committed");
+
+        let message = Message::parse("<13>1 - host app - - -");
+        assert_eq!((message.app.as_deref(), message.line.as_str()), (Some("app"), ""));
+
+        for plain in &["2015-11-09 02:00:00,001 committed", "<13> not syslog", "<13>1 a b"] {
+            assert_eq!(Message::parse(plain),
+                       Message {
+                           app: None,
+                           line: plain.to_string(),
+                       });
+        }
+    }
+
+    #[test]
+    fn can_read_frames() {
+        let mut reader = Cursor::new(concat!("11 <13>1 - h a7 <1>a\r\nb",
+                                             "2015-11-09 02:00:00,001 c\r\n\n42\n"));
+        let mut frame = Vec::new();
+        let mut frames = Vec::new();
+        while read_frame(&mut reader, &mut frame).unwrap() {
+            frames.push(String::from_utf8(frame.clone()).unwrap());
+        }
+        assert_eq!(frames, ["<13>1 - h a", "<1>a\r\nb", "2015-11-09 02:00:00,001 c", "", "42"]);
+    }
+
+    #[test]
+    fn can_read_lines_starting_with_numbers() {
+        let mut reader = Cursor::new("42 items committed\n11 <13>1 - h a42 \n99999999 <1>\n");
+        let mut frame = Vec::new();
+        let mut frames = Vec::new();
+        while read_frame(&mut reader, &mut frame).unwrap() {
+            frames.push(String::from_utf8(frame.clone()).unwrap());
+        }
+        assert_eq!(frames, ["42 items committed", "<13>1 - h a", "42 ", "99999999 <1>"]);
+    }
+
+    #[test]
+    fn can_parse_listen() {
+        assert_eq!("tcp://127.0.0.1:5140".parse::<Listen>().unwrap(),
+                   Listen::Tcp("127.0.0.1:5140".parse().unwrap()));
+        assert!("udp://0.0.0.0:514".parse::<Listen>().is_ok());
+        assert!("http://0.0.0.0:514".parse::<Listen>().is_err());
+        assert!("udp://localhost".parse::<Listen>().is_err());
+    }
+
+    #[test]
+    fn can_receive_from_local_sender() {
+        let (sender, receiver) = channel();
+        let tcp = "tcp://127.0.0.1:0".parse::<Listen>().unwrap().start(sender.clone()).unwrap();
+        let udp = "udp://127.0.0.1:0".parse::<Listen>().unwrap().start(sender).unwrap();
+
+        let mut stream = TcpStream::connect(tcp).unwrap();
+        stream.write_all(b"2015-11-09 02:00:00,001 a\n21 <13>1 - h app - - - b").unwrap();
+        drop(stream);
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.send_to(b"2015-11-09 02:00:00,001 c\n2015-11-09 02:00:00,001 d\n", udp).unwrap();
+
+        let mut received: Vec<_> = (0..4)
+                                       .map(|_| {
+                                           receiver.recv_timeout(Duration::from_secs(5)).unwrap()
+                                       })
+                                       .collect();
+        received.sort_by(|a, b| a.line.cmp(&b.line));
+        assert_eq!(received[0],
+                   Message {
+                       app: None,
+                       line: "2015-11-09 02:00:00,001 a".to_owned(),
+                   });
+        assert_eq!(received[1].line, "2015-11-09 02:00:00,001 c");
+        assert_eq!(received[2].line, "2015-11-09 02:00:00,001 d");
+        assert_eq!((received[3].app.as_deref(), received[3].line.as_str()), (Some("app"), "b"));
+    }
+}
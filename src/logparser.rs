@@ -1,13 +1,31 @@
-use regex::{Regex, Match};
+use regex::Regex;
 use logresult::*;
-use error::LogError;
+use error::*;
+use output::Output;
+use history::History;
+use logstat::{LogTime, Stat};
+use explain::{Explanation, Stage};
+use fields::FieldMatcher;
+use filter::Filter;
+use dedup::Dedup;
+use sequence::Sequences;
+use pairing::Pairing;
 
 
 pub struct LogParser<'tc> {
     matcher: MatcherEnum,
-    result: Box<ResultTrait + Send + 'tc>,
+    result: Box<dyn ResultTrait + Send + 'tc>,
     batch_matcher: Option<MatcherEnum>,
+    /// the start lines of the messages, the matcher is then the end lines, paired by the id
+    start_matcher: Option<MatcherEnum>,
+    pairing: Pairing,
     time_regex: Regex,
+    /// only the lines selected by the filter go to the matchers
+    filter: Option<Filter>,
+    /// drops the lines with an id already seen
+    dedup: Option<Dedup>,
+    /// the last seq capture of every partition in the current file
+    sequences: Sequences,
 }
 
 impl<'tc> LogParser<'tc> {
@@ -16,7 +34,12 @@ impl<'tc> LogParser<'tc> {
             matcher: pattern.to_matcher(),
             result: Box::new(HourResult::new()),
             batch_matcher: None,
+            start_matcher: None,
+            pairing: Pairing::default(),
             time_regex: Regex::new(r"^([^,]+),").unwrap(),
+            filter: None,
+            dedup: None,
+            sequences: Sequences::default(),
         }
     }
 
@@ -28,55 +51,291 @@ impl<'tc> LogParser<'tc> {
                 Some(_) => Box::new(BatchResult::new()),
             },
             batch_matcher: batch.map(|t| t.to_matcher()),
+            start_matcher: None,
+            pairing: Pairing::default(),
             time_regex: Regex::new(r"^([^,]+),").unwrap(),
+            filter: None,
+            dedup: None,
+            sequences: Sequences::default(),
         }
     }
 
-    /// extract_times use match_line to verify the line and extract the watermark from it.
+    /// new_pair pairs the lines of the start matcher with the lines of the end matcher by their
+    /// id capture, for the latency of every message. The end lines are counted as usual.
+    pub fn new_pair<T: ToMatcher, P: ToMatcher>(start: T, end: P) -> LogParser<'tc> {
+        LogParser { start_matcher: Some(start.to_matcher()), ..LogParser::new(end) }
+    }
+
+    /// extract_info use match_line to verify the line and extract the captures from it.
     /// If the input line is the expected line, then also call get_timestamp to extract the
-    /// time stamp.We need both timestamp and watermark to update the result set.
-    pub fn extract_info<'a>(&mut self,
-                            line: &'a str)
-                            -> (Option<&'a str>, Option<Match<'a>>, Option<Match<'a>>) {
-        match self.matcher.match_line(line) {
-            Ok((r, c)) => {
-                let t = self.get_timestamp(line);
-                (Some(t), r, c)
-            }
-            _ => (None, None, None),
+    /// time stamp, unless the matcher captures it. We need both timestamp and watermark to
+    /// update the result set.
+    pub fn extract_info<'a>(&self, line: &'a str) -> Option<LineInfo<'a>> {
+        if !self.is_selected(line) {
+            return None;
+        }
+        self.matcher.match_line(line).ok().map(|mut info| {
+            info.time = info.time.or_else(|| Some(self.get_timestamp(line)));
+            info
+        })
+    }
+
+    /// extract_batch returns the captures of the batch matcher, None if there is no batch matcher.
+    pub fn extract_batch<'a>(&self, line: &'a str) -> Option<Result<LineInfo<'a>>> {
+        if !self.is_selected(line) {
+            return self.batch_matcher.as_ref().map(|_| Err(LogError::MisMatch));
+        }
+        self.batch_matcher.as_ref().map(|p| {
+            p.match_batch(line).map(|mut info| {
+                info.time = info.time.or_else(|| Some(self.get_timestamp(line)));
+                info
+            })
+        })
+    }
+
+    /// extract_start returns the captures of the start matcher, None if there is no start
+    /// matcher or the line doesn't match it.
+    fn extract_start<'a>(&self, line: &'a str) -> Option<LineInfo<'a>> {
+        if !self.is_selected(line) {
+            return None;
         }
+        self.start_matcher.as_ref().and_then(|p| p.match_line(line).ok()).map(|mut info| {
+            info.time = info.time.or_else(|| Some(self.get_timestamp(line)));
+            info
+        })
     }
 
     /// process_line consumes a single
     /// it will extract the information from input and save into result.
-    /// it will return None if the line doesn't match any pattern.
+    /// it will return None if the line doesn't match any pattern, has a bad count, or is a
+    /// duplicate.
     pub fn process_line(&mut self, line: &str) -> Option<usize> {
         match self.extract_info(line) {
-            (Some(time), Some(count), Some(spent)) => {
-                self.result.increase_count(time, spent.as_str(), count.as_str().parse::<usize>().unwrap_or(1))
+            Some(ref info) if self.is_duplicate(info) => {
+                if let Some(stat) = self.result.bucket_stat(info.time.unwrap_or("")) {
+                    stat.duplicates += 1;
+                }
+                None
+            }
+            Some(info) => {
+                // dropped, same as `Stage::BadCount` of the explain mode
+                let works = match info.count.map(|c| c.parse()) {
+                    Some(Ok(c)) => c,
+                    Some(Err(_)) => return None,
+                    None => 1,
+                };
+                let count = self.result
+                                .increase_count(info.time.unwrap_or(""),
+                                                info.watermark.unwrap_or(""),
+                                                works,
+                                                info.spent.unwrap_or(""),
+                                                info.dimension.unwrap_or(""));
+                self.check_sequence(&info);
+                self.check_pair(&info, false);
+                count
             }
-            (Some(time), Some(watermark), None) => self.result.increase_count(time, watermark.as_str(), 1),
-            (Some(time), None, None) => self.result.increase_count(time, "", 1),
-            _ => {
-                self.check_batch(line);
+            None => {
+                match self.extract_start(line) {
+                    Some(info) => self.check_pair(&info, true),
+                    None => self.check_batch(line),
+                }
                 None
             }
         }
     }
 
-    fn check_batch(&mut self, line: &str) {
-        if let Some(ref p) = self.batch_matcher {
+    /// is_duplicate checks the id of the line with the dedup window, lines without an id or a
+    /// valid time stamp are never duplicates.
+    fn is_duplicate(&mut self, info: &LineInfo) -> bool {
+        match (self.dedup.as_mut(), info.id, info.time.map(|t| t.parse::<LogTime>())) {
+            (Some(dedup), Some(id), Some(Ok(time))) => dedup.is_duplicate(id, time.seconds()),
+            _ => false,
+        }
+    }
+
+    /// seen_before is `is_duplicate` without recording the id, for the explain mode
+    fn seen_before(&self, info: &LineInfo) -> bool {
+        match (self.dedup.as_ref(), info.id, info.time.map(|t| t.parse::<LogTime>())) {
+            (Some(dedup), Some(id), Some(Ok(time))) => dedup.contains(id, time.seconds()),
+            _ => false,
+        }
+    }
+
+    /// check_sequence follows the seq capture of the line within its partition, the gaps, out of
+    /// order and resets are counted into the hour/batch of the line.
+    fn check_sequence(&mut self, info: &LineInfo) {
+        if let Some(seq) = info.seq.and_then(|s| s.parse::<u64>().ok()) {
+            let partition = info.partition.unwrap_or("");
+            let event = self.sequences.next(partition, seq);
+            if let Some(stat) = self.result.bucket_stat(info.time.unwrap_or("")) {
+                stat.sequence.record(partition, event);
+            }
+        }
+    }
 
-            match p.match_batch(line) {
-                Ok((Some(r), Some(c))) => {
-                    let t = self.get_timestamp(line);
-                    self.result.process_batch(t, r.as_str(), c.as_str())
+    /// check_pair pairs the start/end line with the other line of the message. The latency goes
+    /// to the hour/batch of the end line, a line waiting for the other one is unmatched until
+    /// then.
+    fn check_pair(&mut self, info: &LineInfo, start: bool) {
+        if self.start_matcher.is_none() {
+            return;
+        }
+        let time = info.time.unwrap_or("");
+        let (id, seconds) = match (info.id, time.parse::<LogTime>()) {
+            (Some(id), Ok(t)) => (id, t.seconds()),
+            _ => return,
+        };
+        let paired = if start {
+            self.pairing.start(id, seconds, time)
+        } else {
+            self.pairing.end(id, seconds, time)
+        };
+        match paired {
+            Some((other, latency)) => {
+                let end = if start { &other } else { time };
+                if let Some(stat) = self.result.bucket_stat(end) {
+                    stat.latency.record(latency, &[]);
+                }
+                if let Some(stat) = self.result.bucket_stat(&other) {
+                    if start {
+                        stat.unmatched_ends = stat.unmatched_ends.saturating_sub(1);
+                    } else {
+                        stat.unmatched_starts = stat.unmatched_starts.saturating_sub(1);
+                    }
+                }
+            }
+            None => {
+                if let Some(stat) = self.result.bucket_stat(time) {
+                    if start {
+                        stat.unmatched_starts += 1;
+                    } else {
+                        stat.unmatched_ends += 1;
+                    }
                 }
-                Ok((Some(c), None)) if c.as_str().parse::<usize>().unwrap_or(0) > 0 => {
-                    let t = self.get_timestamp(line);
-                    self.result.process_batch(t, "", c.as_str())
+            }
+        }
+    }
+
+    /// explain_line processes the line same as `process_line`, and returns how the line went
+    /// through the matchers, for the explain mode.
+    pub fn explain_line<'a>(&mut self, line: &'a str) -> Explanation<'a> {
+        let mut duplicate = false;
+        let (matcher, info, count_ok) = match self.extract_info(line) {
+            Some(info) => {
+                let count_ok = info.count.is_none_or(|c| c.parse::<usize>().is_ok());
+                duplicate = self.seen_before(&info);
+                (Some("line"), info, count_ok)
+            }
+            None => {
+                match (self.extract_start(line), self.extract_batch(line)) {
+                    (Some(info), _) => (Some("start"), info, true),
+                    (None, Some(Ok(info))) => {
+                        let count_ok = info.is_batch_start();
+                        (Some("batch"), info, count_ok)
+                    }
+                    _ => (None, LineInfo::default(), false),
                 }
-                _ => return,
+            }
+        };
+        let timestamp = info.time.unwrap_or_else(|| self.get_timestamp(line));
+        let time = timestamp.parse::<LogTime>().ok();
+        self.process_line(line);
+        Explanation {
+            matcher,
+            captures: info.captures().into_iter().filter(|c| c.0 != "time").collect(),
+            timestamp,
+            bucket: matcher.and_then(|_| self.result.bucket(timestamp)),
+            stage: match (matcher, &time) {
+                (None, _) => Stage::NoMatch,
+                (_, &None) => Stage::BadTimestamp,
+                _ if duplicate => Stage::Duplicate,
+                _ if !count_ok => Stage::BadCount,
+                _ => Stage::Accepted,
+            },
+            time,
+        }
+    }
+
+    /// columns are the optional text columns of the captures of the line matcher, and of the
+    /// latency if the lines are paired
+    pub fn columns(&self) -> Columns {
+        let names = self.matcher.capture_names();
+        let has = |name| names.contains(&Some(name));
+        Columns {
+            lines: has("count"),
+            spent: has("spent"),
+            sequence: has("seq"),
+            latency: self.start_matcher.is_some(),
+            ..Columns::default()
+        }
+    }
+
+    /// is_batch tells whether the result is keyed by batch instead of by hour
+    pub fn is_batch(&self) -> bool {
+        self.batch_matcher.is_some()
+    }
+
+    /// validate checks the named captures of the matchers: no unnamed or unknown group, the
+    /// batch matcher has the total, and the start and end matchers have the id.
+    pub fn validate(&self) -> Result<()> {
+        match self.start_matcher {
+            Some(ref p) => {
+                p.validate(&LINE_CAPTURES, &["id"])?;
+                self.matcher.validate(&LINE_CAPTURES, &["id"])?;
+            }
+            None => self.matcher.validate(&LINE_CAPTURES, &[])?,
+        }
+        match self.batch_matcher {
+            Some(ref p) => p.validate(&BATCH_CAPTURES, &["total"]),
+            None => Ok(()),
+        }
+    }
+
+    /// Only the lines selected by the filter are matched, for example to drop the dry-run lines.
+    /// A filter set before, as the one of the monitor config, must also select the lines.
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = Some(match self.filter.take() {
+            Some(before) => before.and(&filter),
+            None => filter,
+        });
+    }
+
+    fn is_selected(&self, line: &str) -> bool {
+        self.filter.as_ref().is_none_or(|f| f.is_match(line))
+    }
+
+    /// Replaces the regex of the time stamp at the beginning of the lines, the first capture is
+    /// the time stamp.
+    pub fn set_time_regex(&mut self, time_regex: Regex) {
+        self.time_regex = time_regex;
+    }
+
+    /// patterns returns the regexes of the matchers, a line matching none of them is ignored by
+    /// the parser. None if a matcher can't be expressed as a regex.
+    pub fn patterns(&self) -> Option<Vec<String>> {
+        let mut patterns = vec![self.matcher.regex_source()?];
+        for p in self.batch_matcher.iter().chain(self.start_matcher.iter()) {
+            patterns.push(p.regex_source()?);
+        }
+        Some(patterns)
+    }
+
+    /// counts returns (hour/batch, total, done, lines) of every hour/batch in the result
+    pub fn counts(&self) -> Vec<(usize, u32, u32, u32)> {
+        self.result.counts()
+    }
+
+    /// stats returns the stat of every hour/batch in the result, oldest first
+    pub fn stats(&self) -> Vec<(usize, &Stat)> {
+        self.result.stats()
+    }
+
+    fn check_batch(&mut self, line: &str) {
+        if let Some(Ok(info)) = self.extract_batch(line) {
+            if info.is_batch_start() {
+                self.result.process_batch(info.time.unwrap_or(""),
+                                          info.batch_id.unwrap_or(""),
+                                          info.total.unwrap_or(""))
             }
         }
     }
@@ -92,23 +351,135 @@ impl<'tc> LogParser<'tc> {
     }
 
     pub fn wrap_up_file(&mut self) -> usize {
+        self.sequences.clear();
         self.result.wrap_up_file()
     }
 
-    pub fn print_result(&self, name: &str) {
-        self.result.print_result(name);
+    /// wrap_up_interval is `wrap_up_file` for a stream, the lines keep following each other so
+    /// the current batch and the sequences go on. Only the `keep` newest hours/batches are kept.
+    pub fn wrap_up_interval(&mut self, keep: usize) {
+        self.result.prune(keep);
+    }
+
+    pub fn set_options(&mut self, options: ResultOptions) {
+        self.dedup = options.dedup.map(Dedup::new);
+        self.result.set_options(options);
+    }
+
+    pub fn print_result(&self, name: &str, out: &mut Output) {
+        self.result.print_result(name, out);
+    }
+
+    pub fn record_history(&self, name: &str, history: &mut History) {
+        self.result.record_history(name, history);
+    }
+}
+
+/// the named captures of the line matcher
+pub const LINE_CAPTURES: [&str; 8] =
+    ["time", "watermark", "count", "spent", "dimension", "id", "seq", "partition"];
+/// the named captures of the batch matcher
+pub const BATCH_CAPTURES: [&str; 3] = ["time", "batch_id", "total"];
+
+/// `LineInfo` is what a matcher extracts from a line, by the named captures:
+/// `time` overrides the time stamp at the beginning of the line, `watermark` is the time the
+/// message was produced, `count` is how many messages the line stands for, `spent` is how long
+/// the line took to process, `dimension` is the value the counts are broken down by (client,
+/// queue...), `id` identifies the message for the deduplication, `seq` is the sequence number of
+/// the message within its `partition`, `batch_id` and `total` are the id and size of the batch
+/// started by the line.
+#[derive(Debug, Default, PartialEq)]
+pub struct LineInfo<'a> {
+    pub time: Option<&'a str>,
+    pub watermark: Option<&'a str>,
+    pub count: Option<&'a str>,
+    pub spent: Option<&'a str>,
+    pub dimension: Option<&'a str>,
+    pub id: Option<&'a str>,
+    pub seq: Option<&'a str>,
+    pub partition: Option<&'a str>,
+    pub batch_id: Option<&'a str>,
+    pub total: Option<&'a str>,
+}
+
+impl<'a> LineInfo<'a> {
+    fn from_captures(c: &::regex::Captures<'a>) -> LineInfo<'a> {
+        let get = |name| c.name(name).map(|m| m.as_str());
+        LineInfo {
+            time: get("time"),
+            watermark: get("watermark"),
+            count: get("count"),
+            spent: get("spent"),
+            dimension: get("dimension"),
+            id: get("id"),
+            seq: get("seq"),
+            partition: get("partition"),
+            batch_id: get("batch_id"),
+            total: get("total"),
+        }
+    }
+
+    /// sets the capture by its name, unknown names are ignored
+    pub fn set(&mut self, name: &str, value: &'a str) {
+        match name {
+            "time" => self.time = Some(value),
+            "watermark" => self.watermark = Some(value),
+            "count" => self.count = Some(value),
+            "spent" => self.spent = Some(value),
+            "dimension" => self.dimension = Some(value),
+            "id" => self.id = Some(value),
+            "seq" => self.seq = Some(value),
+            "partition" => self.partition = Some(value),
+            "batch_id" => self.batch_id = Some(value),
+            "total" => self.total = Some(value),
+            _ => {}
+        }
+    }
+
+    /// Returns the captured values with their names.
+    pub fn captures(&self) -> Vec<(&'static str, &'a str)> {
+        [("time", self.time),
+         ("watermark", self.watermark),
+         ("count", self.count),
+         ("spent", self.spent),
+         ("dimension", self.dimension),
+         ("id", self.id),
+         ("seq", self.seq),
+         ("partition", self.partition),
+         ("batch_id", self.batch_id),
+         ("total", self.total)]
+            .iter()
+            .filter_map(|&(name, value)| value.map(|v| (name, v)))
+            .collect()
+    }
+
+    /// a batch line starts a batch if it has an id, or a positive total
+    pub fn is_batch_start(&self) -> bool {
+        match (self.batch_id, self.total) {
+            (Some(_), Some(total)) => total.parse::<u32>().is_ok(),
+            (None, Some(total)) => total.parse::<usize>().unwrap_or(0) > 0,
+            _ => false,
+        }
     }
 }
 
 pub enum MatcherEnum {
     Regex(Regex),
     Pattern(String),
+    Fields(FieldMatcher),
+    Filter(Filter),
 }
 
 pub trait ToMatcher {
     fn to_matcher(self) -> MatcherEnum;
 }
 
+impl ToMatcher for MatcherEnum {
+    fn to_matcher(self) -> MatcherEnum {
+        self
+    }
+}
+
 impl ToMatcher for Regex {
     fn to_matcher(self) -> MatcherEnum {
         MatcherEnum::Regex(self)
@@ -121,26 +492,44 @@ impl ToMatcher for String {
     }
 }
 
-impl<'a> ToMatcher for &'a str {
+impl ToMatcher for FieldMatcher {
+    fn to_matcher(self) -> MatcherEnum {
+        MatcherEnum::Fields(self)
+    }
+}
+
+impl ToMatcher for Filter {
+    fn to_matcher(self) -> MatcherEnum {
+        MatcherEnum::Filter(self)
+    }
+}
+
+impl ToMatcher for &str {
     fn to_matcher(self) -> MatcherEnum {
         MatcherEnum::Pattern(self.to_owned())
     }
 }
 
 impl MatcherEnum {
-    pub fn match_line<'a>(&self,
-                          line: &'a str)
-                          -> Result<(Option<Match<'a>>, Option<Match<'a>>), LogError> {
+    pub fn match_line<'a>(&self, line: &'a str) -> Result<LineInfo<'a>> {
         match *self {
             MatcherEnum::Regex(ref r) => {
                 match r.captures(line) {
-                    Some(c) => Ok((c.get(1), c.get(2))),
+                    Some(c) => Ok(LineInfo::from_captures(&c)),
                     None => Err(LogError::MisMatch),
                 }
             }
             MatcherEnum::Pattern(ref r) => {
                 if line.contains(r) {
-                    Ok((None, None))
+                    Ok(LineInfo::default())
+                } else {
+                    Err(LogError::MisMatch)
+                }
+            }
+            MatcherEnum::Fields(ref f) => f.match_line(line).ok_or(LogError::MisMatch),
+            MatcherEnum::Filter(ref f) => {
+                if f.is_match(line) {
+                    Ok(LineInfo::default())
                 } else {
                     Err(LogError::MisMatch)
                 }
@@ -148,17 +537,243 @@ impl MatcherEnum {
         }
     }
 
-    pub fn match_batch<'a>(&self,
-                           line: &'a str)
-                           -> Result<(Option<Match<'a>>, Option<Match<'a>>), LogError> {
+    pub fn match_batch<'a>(&self, line: &'a str) -> Result<LineInfo<'a>> {
         match *self {
             MatcherEnum::Regex(ref r) => {
                 match r.captures(line) {
-                    Some(c) => Ok((c.get(1), c.get(2))),
+                    Some(c) => Ok(LineInfo::from_captures(&c)),
                     None => Err(LogError::MisMatch),
                 }
             }
-            _ => Err(LogError::MisMatch),
+            MatcherEnum::Fields(ref f) => f.match_line(line).ok_or(LogError::MisMatch),
+            MatcherEnum::Pattern(_) | MatcherEnum::Filter(_) => Err(LogError::MisMatch),
         }
     }
+
+    /// the regex matching the same lines, None for the structured matchers and filters
+    fn regex_source(&self) -> Option<String> {
+        match *self {
+            MatcherEnum::Regex(ref r) => Some(r.as_str().to_owned()),
+            MatcherEnum::Pattern(ref p) => Some(::regex::escape(p)),
+            MatcherEnum::Fields(_) | MatcherEnum::Filter(_) => None,
+        }
+    }
+
+    /// the names of the capture groups, None for an unnamed group
+    fn capture_names(&self) -> Vec<Option<&str>> {
+        match *self {
+            MatcherEnum::Regex(ref r) => r.capture_names().skip(1).collect(),
+            MatcherEnum::Pattern(_) | MatcherEnum::Filter(_) => Vec::new(),
+            MatcherEnum::Fields(ref f) => f.capture_names().into_iter().map(Some).collect(),
+        }
+    }
+
+    /// validate checks every capture group is named with one of `known`, and the `required`
+    /// groups exist.
+    pub fn validate(&self, known: &[&str], required: &[&str]) -> Result<()> {
+        let names = self.capture_names();
+        let pattern = match *self {
+            MatcherEnum::Regex(ref r) => r.as_str().to_owned(),
+            MatcherEnum::Pattern(ref p) => p.clone(),
+            MatcherEnum::Fields(ref f) => f.to_string(),
+            MatcherEnum::Filter(ref f) => f.to_string(),
+        };
+        if let Some(index) = names.iter().position(|n| n.is_none()) {
+            return Err(LogError::InvalidCaptures(format!("group {} of \"{}\" is not named, \
+                                                          use (?P<name>...) or (?:...)",
+                                                         index + 1,
+                                                         pattern)));
+        }
+        if let Some(name) = names.iter().flatten().find(|n| !known.contains(n)) {
+            return Err(LogError::InvalidCaptures(format!("unknown group {} in \"{}\", expected \
+                                                          {}",
+                                                         name,
+                                                         pattern,
+                                                         known.join(", "))));
+        }
+        match required.iter().find(|r| !names.contains(&Some(**r))) {
+            Some(name) => {
+                Err(LogError::InvalidCaptures(format!("missing group {} in \"{}\"", name, pattern)))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fields::FieldFormat;
+    use output::Format;
+
+    #[test]
+    fn can_extract_named_captures() {
+        let parser = LogParser::new(Regex::new(r"sent (?P<count>\d+) at (?P<watermark>[^,]+),")
+                                        .unwrap());
+        let info = parser.extract_info("2015-11-09 02:00:00,000 sent 5 at 2015-11-09 01:59:00,")
+                         .unwrap();
+        assert_eq!(info.time, Some("2015-11-09 02:00:00"));
+        assert_eq!(info.captures(),
+                   [("time", "2015-11-09 02:00:00"),
+                    ("watermark", "2015-11-09 01:59:00"),
+                    ("count", "5")]);
+        assert_eq!(parser.extract_info("2015-11-09 02:00:00,000 sent"), None);
+
+        // the time capture overrides the time stamp at the beginning of the line
+        let parser = LogParser::new(Regex::new(r"\[(?P<time>[^\]]+)\] done").unwrap());
+        let info = parser.extract_info("[2015-11-09 02:00:00] done").unwrap();
+        assert_eq!(info.time, Some("2015-11-09 02:00:00"));
+    }
+
+    #[test]
+    fn can_weight_counts() {
+        let mut parser = LogParser::new(Regex::new(r"committed (?P<count>\S+)").unwrap());
+        parser.process_line("2015-11-09 02:00:00,000 committed 5");
+        parser.process_line("2015-11-09 02:01:00,000 committed 0");
+        // a count that doesn't parse drops the line
+        parser.process_line("2015-11-09 02:02:00,000 committed abc");
+        parser.process_line("2015-11-09 02:03:00,000 aborted 7");
+        assert_eq!(parser.counts(), [(2015110902, 0, 5, 2)]);
+    }
+
+    #[test]
+    fn can_drop_bad_counts() {
+        let line = "2015-11-09 02:00:00,000 count=many";
+        let new_parser = || LogParser::new(Regex::new(r"count=(?P<count>\w+)").unwrap());
+
+        let mut parser = new_parser();
+        assert_eq!(parser.process_line(line), None);
+        assert!(parser.counts().is_empty());
+
+        let mut parser = new_parser();
+        assert_eq!(parser.explain_line(line).stage, Stage::BadCount);
+        assert!(parser.counts().is_empty());
+
+        parser.process_line("2015-11-09 02:00:00,000 count=2");
+        assert_eq!(parser.counts(), [(2015110902, 0, 2, 1)]);
+    }
+
+    #[test]
+    fn can_drop_duplicates() {
+        let mut parser = LogParser::new(Regex::new(r"eventId=(?P<id>\d+)").unwrap());
+        parser.set_options(ResultOptions { dedup: Some(600), ..ResultOptions::default() });
+        parser.process_line("2015-11-09 02:00:00,000 eventId=1");
+        parser.process_line("2015-11-09 02:01:00,000 eventId=2");
+        parser.process_line("2015-11-09 02:05:00,000 eventId=1");
+        assert_eq!(parser.explain_line("2015-11-09 02:06:00,000 eventId=2").stage,
+                   Stage::Duplicate);
+        assert_eq!(parser.explain_line("2015-11-09 02:06:00,000 eventId=3").stage,
+                   Stage::Accepted);
+        assert_eq!(parser.explain_line("2015-11-09 02:07:00,000 eventId=3").stage,
+                   Stage::Duplicate);
+        // out of the window
+        parser.process_line("2015-11-09 02:30:00,000 eventId=1");
+        parser.process_line("2015-11-09 02:31:00,000 no id");
+        assert_eq!(parser.counts(), [(2015110902, 0, 4, 4)]);
+
+        let mut parser = LogParser::new(Regex::new(r"eventId=(?P<id>\d+)").unwrap());
+        parser.process_line("2015-11-09 02:00:00,000 eventId=1");
+        parser.process_line("2015-11-09 02:05:00,000 eventId=1");
+        assert_eq!(parser.counts(), [(2015110902, 0, 2, 2)]);
+    }
+
+    #[test]
+    fn can_find_sequence_gaps() {
+        let mut parser = LogParser::new(Regex::new(r"p=(?P<partition>\w+) seq=(?P<seq>\d+)")
+                                            .unwrap());
+        for line in &["2015-11-09 01:59:00,000 p=a seq=1",
+                      "2015-11-09 02:00:00,000 p=a seq=4",
+                      "2015-11-09 02:01:00,000 p=b seq=7",
+                      "2015-11-09 02:02:00,000 p=a seq=3",
+                      "2015-11-09 02:03:00,000 p=b seq=8"] {
+            parser.process_line(line);
+        }
+        let columns = parser.columns();
+        assert_eq!(columns, Columns { sequence: true, ..Columns::default() });
+        parser.set_options(ResultOptions { columns, ..ResultOptions::default() });
+        let mut out = Output::new(Format::Text);
+        parser.print_result("Seq", &mut out);
+        assert!(out.lines[0].ends_with(", 0, 1, 1, 1, 0"));
+        assert_eq!(out.lines[1], "Seq-0,missing, a:2");
+
+        // a new file doesn't follow the previous one
+        parser.wrap_up_file();
+        parser.process_line("2015-11-09 01:00:00,000 p=a seq=100");
+        parser.print_result("Seq", &mut out);
+        assert_eq!(out.lines.len(), 4);
+    }
+
+    #[test]
+    fn can_pair_start_and_end() {
+        let mut parser = LogParser::new_pair(Regex::new(r"received id=(?P<id>\w+)").unwrap(),
+                                             Regex::new(r"committed id=(?P<id>\w+)").unwrap());
+        assert!(parser.validate().is_ok());
+        // the newer file first
+        for line in &["2015-11-09 02:10:00,000 committed id=a",
+                      "2015-11-09 02:11:00,000 received id=c",
+                      "2015-11-09 02:12:00,000 committed id=d"] {
+            parser.process_line(line);
+        }
+        parser.wrap_up_file();
+        for line in &["2015-11-09 02:01:00,000 received id=a",
+                      "2015-11-09 02:02:00,000 received id=b",
+                      "2015-11-09 02:02:30,000 committed id=b"] {
+            parser.process_line(line);
+        }
+        assert_eq!(parser.counts(), [(2015110902, 0, 3, 3)]);
+
+        let stat = parser.result.bucket_stat("2015-11-09 02:00:00").unwrap();
+        assert_eq!((stat.latency.count(), stat.latency.min, stat.latency.max), (2, 30, 540));
+        assert_eq!((stat.unmatched_starts, stat.unmatched_ends), (1, 1));
+        // an hour without counted line isn't a result
+        parser.process_line("2015-11-09 01:59:00,000 received id=e");
+        assert_eq!(parser.counts(), [(2015110902, 0, 3, 3)]);
+        parser.process_line("2015-11-09 01:59:30,000 committed id=f");
+        assert_eq!(parser.counts(), [(2015110901, 0, 1, 1), (2015110902, 0, 3, 3)]);
+        let stat = parser.result.bucket_stat("2015-11-09 01:00:00").unwrap();
+        assert_eq!((stat.unmatched_starts, stat.unmatched_ends), (1, 1));
+
+        let parser = LogParser::new_pair(Regex::new(r"received").unwrap(),
+                                         Regex::new(r"committed id=(?P<id>\w+)").unwrap());
+        assert!(parser.validate().is_err());
+        assert_eq!(parser.patterns().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn can_filter_lines() {
+        let mut parser = LogParser::new(Regex::new(r"docWriteTime=(?P<watermark>[^}]+)}").unwrap());
+        parser.set_filter(r#"NOT "dry-run""#.parse().unwrap());
+        assert!(parser.extract_info("2015-11-09 02:00:00,000 {docWriteTime=x}").is_some());
+        assert!(parser.extract_info("2015-11-09 02:00:00,000 dry-run {docWriteTime=x}").is_none());
+        // both filters must select the line
+        parser.set_filter(r#"NOT "replay""#.parse().unwrap());
+        assert!(parser.extract_info("2015-11-09 02:00:00,000 {docWriteTime=x}").is_some());
+        assert!(parser.extract_info("2015-11-09 02:00:00,000 dry-run {docWriteTime=x}").is_none());
+        assert!(parser.extract_info("2015-11-09 02:00:00,000 replay {docWriteTime=x}").is_none());
+
+        let parser = LogParser::new(r#""committed" AND level == INFO"#.parse::<Filter>().unwrap());
+        assert!(parser.extract_info("2015-11-09 02:00:00,000 INFO committed").is_some());
+        assert!(parser.extract_info("2015-11-09 02:00:00,000 DEBUG committed").is_none());
+    }
+
+    #[test]
+    fn can_validate_captures() {
+        let batch = |line: &str, batch: &str| {
+            LogParser::new_batch(Regex::new(line).unwrap(), Some(Regex::new(batch).unwrap()))
+                .validate()
+                .map_err(|e| e.to_string())
+        };
+        assert!(batch(r"committed", r"Context contains (?P<total>\d+)").is_ok());
+        assert!(batch(r"id=(?P<batch_id>\w+) size=(?P<total>\d+)", r"(?P<total>\d+)").is_err());
+        assert_eq!(batch(r"committed", r"Context contains (\d+)").unwrap_err(),
+                   "Invalid Captures: group 1 of \"Context contains (\\d+)\" is not named, use \
+                    (?P<name>...) or (?:...)");
+        assert_eq!(batch(r"committed", r"batch (?P<batch_id>\d+)").unwrap_err(),
+                   "Invalid Captures: missing group total in \"batch (?P<batch_id>\\d+)\"");
+        assert!(LogParser::new(Regex::new(r"(?P<elapsed>\d+)").unwrap()).validate().is_err());
+        assert!(LogParser::new(Regex::new(r"took (?P<spent>\S+)").unwrap()).validate().is_ok());
+        assert!(LogParser::new("committed").validate().is_ok());
+        let fields = FieldMatcher::new(FieldFormat::Json).capture("elapsed", "elapsed");
+        assert!(LogParser::new(fields).validate().is_err());
+    }
 }
@@ -1,7 +1,9 @@
-use std::collections::HashMap;
-use std::hash::BuildHasherDefault;
-use fnv::FnvHasher;
-use logstat::Stat;
+use std::collections::BTreeMap;
+use std::collections::btree_map::Range;
+use std::collections::Bound::{Excluded, Unbounded};
+use logstat::{Stat, hms, hour_minutes, minutes_to_str};
+use output::{Format, Output, TEXT_HEADER, json_str};
+use history::{AnomalyOptions, Expected, History, Sample};
 
 pub fn trim_index(index: &str) -> usize {
     String::from_utf8(index.bytes().filter(|c| *c >= b'0' && *c <= b'9').collect::<Vec<_>>())
@@ -10,17 +12,268 @@ pub fn trim_index(index: &str) -> usize {
         .unwrap_or(0)
 }
 
+/// `ResultOptions` holds the settings shared by every result type.
+#[derive(Debug, Clone, Default)]
+pub struct ResultOptions {
+    /// upper bounds of the delay histogram buckets in seconds, empty if histogram is not required
+    pub buckets: Vec<i64>,
+    /// report the periods without any matching line at least this long, in seconds
+    pub stall: Option<i64>,
+    /// compare every hour with the same hour of the previous weeks
+    pub anomaly: Option<AnomalyOptions>,
+    /// how many of the most counted dimension values to report per hour/batch, 0 to not report
+    pub top: usize,
+    /// drop the lines with an id already seen within this window, in seconds
+    pub dedup: Option<i64>,
+    /// the optional columns of the text output
+    pub columns: Columns,
+}
+
+/// `Columns` are the optional groups of columns of the text output, after the columns of
+/// `TEXT_HEADER`. A group is only shown if its capture or option is used, so every row has the
+/// same columns as the header.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Columns {
+    /// the delay distribution, with the histogram
+    pub delays: bool,
+    /// the matched lines, with the count capture
+    pub lines: bool,
+    /// the processing times, with the spent capture
+    pub spent: bool,
+    /// the lines dropped by the dedup window
+    pub duplicates: bool,
+    /// the gaps of the seq capture
+    pub sequence: bool,
+    /// the latency and the unmatched lines of the paired lines
+    pub latency: bool,
+}
+
+impl Columns {
+    /// union has the groups of both
+    pub fn union(self, other: Columns) -> Columns {
+        Columns {
+            delays: self.delays || other.delays,
+            lines: self.lines || other.lines,
+            spent: self.spent || other.spent,
+            duplicates: self.duplicates || other.duplicates,
+            sequence: self.sequence || other.sequence,
+            latency: self.latency || other.latency,
+        }
+    }
+
+    /// the CSV header of the text output
+    pub fn header(&self) -> String {
+        let mut header = TEXT_HEADER.to_owned();
+        let groups = [(self.delays, "DelayMin, DelayMax, DelayMean, DelayP50, DelayP90, DelayP99"),
+                      (self.lines, "Lines"),
+                      (self.spent,
+                       "SpentMin(ms), SpentMax(ms), SpentMean(ms), SpentP50(ms), SpentP90(ms), \
+                        SpentP99(ms)"),
+                      (self.duplicates, "Duplicates"),
+                      (self.sequence, "Gaps, Missing, OutOfOrder, Resets"),
+                      (self.latency,
+                       "LatencyMin, LatencyMax, LatencyMean, LatencyP50, LatencyP90, LatencyP99, \
+                        UnmatchedStarts, UnmatchedEnds")];
+        for (_, group) in groups.iter().filter(|g| g.0) {
+            header.push_str(", ");
+            header.push_str(group);
+        }
+        header
+    }
+
+    /// the values of the groups of the stat, each prefixed by ", "
+    fn values(&self, stat: &Stat) -> String {
+        let groups = [(self.delays, stat.delays.to_str()),
+                      (self.lines, stat.lines.to_string()),
+                      (self.spent, stat.spent.format(|v| v.to_string())),
+                      (self.duplicates, stat.duplicates.to_string()),
+                      (self.sequence, stat.sequence.to_str()),
+                      (self.latency,
+                       format!("{}, {}, {}",
+                               stat.latency.to_str(),
+                               stat.unmatched_starts,
+                               stat.unmatched_ends))];
+        groups.iter().filter(|g| g.0).map(|g| format!(", {}", g.1)).collect()
+    }
+}
+
+/// `Stall` is a period without any matching line, in minutes since epoch.
+/// start is the first silent minute, end is the minute the lines come back.
+#[derive(Debug, PartialEq)]
+pub struct Stall {
+    pub start: i64,
+    pub end: i64,
+}
+
+impl Stall {
+    pub fn seconds(&self) -> i64 {
+        (self.end - self.start) * 60
+    }
+
+    fn report(&self, out: &mut Output, name: &str) {
+        match out.format {
+            Format::Text => {
+                out.line(format!("{}-stall,{}, {}, {}",
+                                 name,
+                                 minutes_to_str(self.start),
+                                 minutes_to_str(self.end),
+                                 hms(self.seconds())))
+            }
+            Format::Json => {
+                out.line(format!(r#"{{"name":{},"stall":{{"start":{},"end":{},"seconds":{}}}}}"#,
+                                 json_str(name),
+                                 json_str(&minutes_to_str(self.start)),
+                                 json_str(&minutes_to_str(self.end)),
+                                 self.seconds()))
+            }
+            Format::Prometheus => {
+                let start = minutes_to_str(self.start);
+                out.metric("tc_stall_seconds",
+                           "gauge",
+                           "",
+                           &[("monitor", name), ("start", &start)],
+                           self.seconds() as f64)
+            }
+        }
+    }
+}
+
 pub trait ResultTrait {
-    fn increase_count(&mut self, time: &str, watermark: &str, count: usize) -> Option<usize>;
+    fn increase_count(&mut self,
+                      time: &str,
+                      watermark: &str,
+                      count: usize,
+                      spent: &str,
+                      dimension: &str)
+                      -> Option<usize>;
     fn wrap_up_file(&mut self) -> usize;
+    /// bucket_stat returns the stat of the hour/batch a line with the time stamp is counted into.
+    fn bucket_stat(&mut self, time: &str) -> Option<&mut Stat>;
     fn process_batch(&mut self, _: &str, _: &str, _: &str) {}
-    fn print_result(&self, name: &str);
+    fn set_options(&mut self, options: ResultOptions);
+    fn print_result(&self, name: &str, out: &mut Output);
+    /// record_history saves the result into the history, for the baseline of the next runs.
+    fn record_history(&self, _: &str, _: &mut History) {}
+    /// bucket returns the hour/batch a line with the time stamp is counted into.
+    fn bucket(&self, _: &str) -> Option<usize> {
+        None
+    }
+    /// counts returns (hour/batch, total, done, lines) of every hour/batch
+    fn counts(&self) -> Vec<(usize, u32, u32, u32)>;
+    /// stats returns the stat of every hour/batch, oldest first
+    fn stats(&self) -> Vec<(usize, &Stat)>;
+    /// prune keeps the `keep` newest hours/batches of a result fed by a stream, which otherwise
+    /// grows for ever.
+    fn prune(&mut self, keep: usize);
+}
+
+/// keep_newest removes the oldest records over `keep`
+fn keep_newest(map: &mut BTreeMap<usize, Stat>, keep: usize) {
+    while map.len() > keep {
+        map.pop_first();
+    }
+}
+
+/// `Row` is a single hour/batch of a monitor, as reported.
+struct Row<'a> {
+    name: &'a str,
+    /// the position of the row, newest first
+    count: usize,
+    /// the hour/batch index, labeled by `key_name`
+    key_name: &'static str,
+    key: usize,
+    /// the row for text format
+    text: String,
+    stat: &'a Stat,
+    efficiency: f32,
+    /// the last sample delay, only available for hour result
+    delay: Option<String>,
+}
+
+/// report adds a single hour/batch row into the output.
+fn report(out: &mut Output, row: Row, options: &ResultOptions) {
+    let Row { name, count, key_name, key, stat, .. } = row;
+    let buckets = &options.buckets;
+    match out.format {
+        Format::Text => {
+            out.line(format!("{}-{},{}{}", name, count, row.text, options.columns.values(stat)));
+            if !stat.sequence.ranges.is_empty() {
+                out.line(format!("{}-{},missing, {}", name, count, stat.sequence.ranges_str()));
+            }
+            if !buckets.is_empty() {
+                out.line(stat.delays.histogram_chart(buckets));
+            }
+        }
+        Format::Json => out.line(stat.to_json(name, count, row.efficiency, row.delay, buckets)),
+        Format::Prometheus => {
+            let key = key.to_string();
+            stat.to_metrics(out, &[("monitor", name), (key_name, &key)], row.efficiency, buckets)
+        }
+    }
+    if options.top > 0 && !stat.dimensions.is_empty() {
+        report_top(out, name, count, (key_name, key), stat, options.top);
+    }
+}
+
+/// report_top adds the `n` most counted dimension values of a hour/batch, and the count of all
+/// the others.
+fn report_top(out: &mut Output,
+              name: &str,
+              count: usize,
+              (key_name, key): (&str, usize),
+              stat: &Stat,
+              n: usize) {
+    let top = stat.dimensions.top(n);
+    let other = stat.dimensions.other(n);
+    match out.format {
+        Format::Text => {
+            let columns: Vec<_> = top.iter().map(|t| format!("{} {}", t.0, t.1)).collect();
+            out.line(format!("{}-{},top, {}, other {}", name, count, columns.join(", "), other));
+        }
+        Format::Json => {
+            let values: Vec<_> = top.iter()
+                                    .map(|t| {
+                                        format!(r#"{{"value":{},"count":{},"error":{}}}"#,
+                                                json_str(t.0),
+                                                t.1,
+                                                t.2)
+                                    })
+                                    .collect();
+            out.line(format!(r#"{{"name":{},"index":{},"top":[{}],"other":{}}}"#,
+                             json_str(name),
+                             count,
+                             values.join(","),
+                             other));
+        }
+        Format::Prometheus => {
+            let key = key.to_string();
+            for t in &top {
+                out.metric("tc_top_done",
+                           "gauge",
+                           "",
+                           &[("monitor", name), (key_name, &key), ("value", t.0)],
+                           t.1 as f64);
+            }
+            out.metric("tc_top_other_done",
+                       "gauge",
+                       "",
+                       &[("monitor", name), (key_name, &key)],
+                       other as f64);
+        }
+    }
 }
 
-/// `HourResult` is simply just a `HashMap`, using the log hour (usize, for example "2015 09") as 
-/// index and Stat as content.
+/// `HourResult` is simply just a `BTreeMap`, using the log hour (usize, for example 2015110902)
+/// as index and Stat as content. The map keeps the hours in order, so the oldest record is always
+/// the first one.
 #[derive(Default)]
-pub struct HourResult(pub HashMap<usize, Stat, BuildHasherDefault<FnvHasher>>);
+pub struct HourResult {
+    pub hours: BTreeMap<usize, Stat>,
+    /// the hours without any counted line yet, for example with an unmatched start line only.
+    /// They are moved to `hours` by their first counted line.
+    uncounted: BTreeMap<usize, Stat>,
+    options: ResultOptions,
+}
 
 impl HourResult {
     pub fn new() -> HourResult {
@@ -28,11 +281,130 @@ impl HourResult {
     }
 
     /// Returns the keys without the oldest record
+    #[cfg(test)]
     fn get_result(&self) -> Vec<usize> {
-        // self.0.keys().cloned().skip(1).collect()
-        let mut keys: Vec<_> = self.0.keys().cloned().collect();
-        keys.sort();
-        keys.iter().skip(1).cloned().collect()
+        self.complete().map(|(k, _)| *k).collect()
+    }
+
+    /// Returns the hours without the oldest one, oldest first. The oldest hour is normally cut by
+    /// the start of the log files, so it is likely to be incomplete.
+    pub fn complete(&self) -> Range<'_, usize, Stat> {
+        match self.hours.keys().next() {
+            Some(&oldest) => self.hours.range((Excluded(oldest), Unbounded)),
+            None => self.hours.range(..),
+        }
+    }
+
+    /// Returns the last `n` complete hours, newest first.
+    pub fn last_complete(&self, n: usize) -> Vec<(usize, &Stat)> {
+        self.complete().rev().take(n).map(|(k, v)| (*k, v)).collect()
+    }
+
+    /// Returns the periods without any matching line at least `threshold` seconds long, newest
+    /// first. Only the gaps between the first and the last matching line are known, based on the
+    /// per-minute counts, so missing hours are part of the gap as well.
+    pub fn stalls(&self, threshold: i64) -> Vec<Stall> {
+        let mut stalls = Vec::new();
+        let mut last: Option<i64> = None;
+        for (hour, stat) in &self.hours {
+            let base = match hour_minutes(*hour) {
+                Some(b) => b,
+                None => continue,
+            };
+            for (min, _) in stat.minutes.iter().enumerate().filter(|&(_, c)| *c > 0) {
+                let now = base + min as i64;
+                if let Some(l) = last {
+                    let stall = Stall {
+                        start: l + 1,
+                        end: now,
+                    };
+                    if stall.seconds() > 0 && stall.seconds() >= threshold {
+                        stalls.push(stall);
+                    }
+                }
+                last = Some(now);
+            }
+        }
+        stalls.reverse();
+        stalls
+    }
+
+    /// report_baseline compares the done count and the p90 delay of a hour with the same hour of
+    /// the previous weeks, and reports the expected range next to the actual value.
+    fn report_baseline(&self,
+                       out: &mut Output,
+                       name: &str,
+                       count: usize,
+                       (hour, stat): (usize, &Stat),
+                       anomaly: &AnomalyOptions) {
+        let samples = anomaly.history.same_hour(name, hour, anomaly.weeks);
+        let done: Vec<_> = samples.iter().map(|s| f64::from(s.done)).collect();
+        let delay: Vec<_> = samples.iter().filter_map(|s| s.delay).map(|d| d as f64).collect();
+
+        let mut checks: Vec<(&str, f64, Expected)> = Vec::new();
+        if let Some(e) = anomaly.expected(&done) {
+            checks.push(("done", f64::from(stat.done), e));
+        }
+        if let (Some(e), Some(actual)) = (anomaly.expected(&delay), stat.delays.quantile(0.9)) {
+            checks.push(("delay_p90", actual as f64, e));
+        }
+        if checks.is_empty() {
+            return;
+        }
+
+        match out.format {
+            Format::Text => {
+                let columns: Vec<_> = checks.iter()
+                                            .map(|&(metric, actual, ref e)| {
+                                                let show = |v: f64| if metric == "done" {
+                                                    format!("{:.0}", v)
+                                                } else {
+                                                    hms(v.round() as i64)
+                                                };
+                                                format!("{} {} expected {}-{}{}",
+                                                        metric,
+                                                        show(actual),
+                                                        show(e.low),
+                                                        show(e.high),
+                                                        if e.is_anomaly(actual) {
+                                                            " ANOMALY"
+                                                        } else {
+                                                            ""
+                                                        })
+                                            })
+                                            .collect();
+                out.line(format!("{}-{},baseline, {}", name, count, columns.join(", ")));
+            }
+            Format::Json => {
+                let columns: Vec<_> = checks.iter()
+                                            .map(|&(metric, actual, ref e)| {
+                                                format!(r#""{}":{{"actual":{},"low":{:.2},"high":{:.2},"anomaly":{}}}"#,
+                                                        metric,
+                                                        actual,
+                                                        e.low,
+                                                        e.high,
+                                                        e.is_anomaly(actual))
+                                            })
+                                            .collect();
+                out.line(format!(r#"{{"name":{},"index":{},"baseline":{{{}}}}}"#,
+                                 json_str(name),
+                                 count,
+                                 columns.join(",")));
+            }
+            Format::Prometheus => {
+                let hour = hour.to_string();
+                for &(metric, actual, ref e) in &checks {
+                    let labels = [("monitor", name), ("hour", &hour), ("metric", metric)];
+                    out.metric("tc_expected_low", "gauge", "", &labels, e.low);
+                    out.metric("tc_expected_high", "gauge", "", &labels, e.high);
+                    out.metric("tc_anomaly",
+                               "gauge",
+                               "",
+                               &labels,
+                               if e.is_anomaly(actual) { 1.0 } else { 0.0 });
+                }
+            }
+        }
     }
 }
 
@@ -43,9 +415,18 @@ impl ResultTrait for HourResult {
     /// ** Parameters **
     /// time: The timestamp of the log line
     /// watermark: the timestamp of the trade DB write time.
+    /// count: how many works the line stands for.
+    /// spent: the processing time of the line, empty if not captured.
+    /// dimension: the value the works are broken down by, empty if not captured.
     ///
     /// Returns the current count of TcResult, for early exit purpose
-    fn increase_count(&mut self, time: &str, watermark: &str, _: usize) -> Option<usize> {
+    fn increase_count(&mut self,
+                      time: &str,
+                      watermark: &str,
+                      count: usize,
+                      spent: &str,
+                      dimension: &str)
+                      -> Option<usize> {
         let split: Vec<_> = time.split(':').collect();
         let (hour, min): (usize, u32) = match split[..] {
             // [TODO]: Better error handling required - 2015-12-07 10:07P
@@ -53,52 +434,123 @@ impl ResultTrait for HourResult {
             _ => return None,
         };
         {
-            let mut result = self.0
-                                 .entry(hour)
-                                 .or_insert(Stat {
-                                     duration: min,
-                                     last_sample_time: time.to_owned(),
-                                     total: 0,
-                                     done: 0,
-                                     last_time_stamp: watermark.to_owned(),
-                                 });
+            let uncounted = &mut self.uncounted;
+            let result = self.hours
+                             .entry(hour)
+                             .or_insert_with(|| Stat {
+                                 duration: min,
+                                 last_sample_time: time.to_owned(),
+                                 total: 0,
+                                 done: 0,
+                                 last_time_stamp: watermark.to_owned(),
+                                 ..uncounted.remove(&hour).unwrap_or_default()
+                             });
 
-            result.done += 1;
+            result.count_line(count);
+            result.record_delay(time, watermark, &self.options.buckets);
+            result.record_spent(spent);
+            result.record_dimension(dimension, count);
+            // the minutes count lines, a line of 0 works still shows the monitor is alive
+            result.minutes.resize(60, 0);
+            result.minutes[min as usize % 60] += 1;
             if result.duration <= min {
                 result.duration = min;
                 result.last_sample_time = time.to_owned();
                 result.last_time_stamp = watermark.to_owned();
             }
         }
-        Some(self.0.len())
+        Some(self.hours.len())
     }
 
 
     fn wrap_up_file(&mut self) -> usize {
-        self.0.len()
+        self.hours.len()
     }
 
-    fn print_result(&self, name: &str) {
+    fn bucket_stat(&mut self, time: &str) -> Option<&mut Stat> {
+        let hour = self.bucket(time)?;
+        Some(match self.hours.get_mut(&hour) {
+            Some(stat) => stat,
+            None => self.uncounted.entry(hour).or_default(),
+        })
+    }
+
+    fn set_options(&mut self, options: ResultOptions) {
+        self.options = options;
+    }
+
+    fn print_result(&self, name: &str, out: &mut Output) {
         // skip the first value, normally the record too old so likely to be incomplete.
-        for (count, key) in self.get_result().iter().rev().enumerate() {
-            match self.0.get(key) {
-                Some(val) if count == 0 => {
-                    println!("{}-{},{}", name, count, val.to_str(true));
-                }
-                Some(val) => {
-                    println!("{}-{},{}", name, count, val.to_str(false));
-                }
-                None => println!("{}-{},{}", name, count, "missing value"),
+        for (count, (key, val)) in self.last_complete(self.hours.len()).into_iter().enumerate() {
+            let row = Row {
+                name,
+                count,
+                key_name: "hour",
+                key,
+                text: val.to_str(count == 0),
+                stat: val,
+                efficiency: val.efficiency(),
+                delay: match val.delay_time().as_ref() {
+                    "0" => None,
+                    t => Some(t.to_owned()),
+                },
             };
+            report(out, row, &self.options);
+            // the newest hour is still in progress, a partial count is not an anomaly
+            match self.options.anomaly {
+                Some(ref anomaly) if count > 0 => {
+                    self.report_baseline(out, name, count, (key, val), anomaly)
+                }
+                _ => {}
+            }
+        }
+        if let Some(threshold) = self.options.stall {
+            for stall in self.stalls(threshold) {
+                stall.report(out, name);
+            }
+        }
+    }
+
+    /// the oldest and the newest hours are not saved, the oldest is likely to be incomplete and
+    /// the newest is still in progress, either would override a good one saved by a previous run.
+    fn record_history(&self, name: &str, history: &mut History) {
+        let newest = self.hours.keys().next_back();
+        for (hour, stat) in self.complete().filter(|h| Some(h.0) != newest) {
+            history.insert(name,
+                           *hour,
+                           Sample {
+                               done: stat.done,
+                               delay: stat.delays.quantile(0.9),
+                           });
+        }
+    }
+
+    fn counts(&self) -> Vec<(usize, u32, u32, u32)> {
+        self.hours.iter().map(|(k, s)| (*k, s.total, s.done, s.lines)).collect()
+    }
+
+    fn stats(&self) -> Vec<(usize, &Stat)> {
+        self.hours.iter().map(|(k, s)| (*k, s)).collect()
+    }
+
+    fn prune(&mut self, keep: usize) {
+        keep_newest(&mut self.hours, keep);
+        keep_newest(&mut self.uncounted, keep);
+    }
+
+    fn bucket(&self, time: &str) -> Option<usize> {
+        match time.split(':').collect::<Vec<_>>()[..] {
+            [hour, _, _] | [hour, _] => Some(trim_index(hour)),
+            _ => None,
         }
     }
 }
 
 #[derive(Default)]
 pub struct BatchResult {
-    /// HashMap for the batch, reuse Stat to hold the statistic for each batch
+    /// BTreeMap for the batch, reuse Stat to hold the statistic for each batch
     /// usize is the batch start time, is only for batch order
-    map: HashMap<usize, Stat, BuildHasherDefault<FnvHasher>>,
+    map: BTreeMap<usize, Stat>,
 
     /// temp_count should be always zero when start processing a new file. 
     temp_count: Stat,
@@ -109,48 +561,55 @@ pub struct BatchResult {
 
     /// current_batch is the current batch index.
     current_batch: Option<usize>,
+
+    options: ResultOptions,
 }
 
 impl BatchResult {
     pub fn new() -> BatchResult {
         BatchResult::default()
     }
-    /// Returns the keys without the oldest record
-    fn get_result(&self) -> Vec<usize> {
-        // self.0.keys().cloned().skip(1).collect()
-        let mut keys: Vec<_> = self.map.keys().cloned().collect();
-        keys.sort();
-        keys
-    }
 }
 
 impl ResultTrait for BatchResult {
     fn process_batch(&mut self, index: &str, _: &str, total: &str) {
         let total = total.parse::<u32>().unwrap_or(0);
         self.current_batch = Some(trim_index(index));
-        let mut result = self.map
-                             .entry(self.current_batch.unwrap())
-                             .or_insert_with({
-                                 || Stat::new()
-                             });
+        let result = self.map
+                         .entry(self.current_batch.unwrap())
+                         .or_insert_with({
+                             || Stat::new()
+                         });
         result.total = total;
         result.last_sample_time = index.to_owned();
     }
 
-    fn increase_count(&mut self, time: &str, _: &str, _: usize) -> Option<usize> {
+    fn increase_count(&mut self,
+                      time: &str,
+                      watermark: &str,
+                      count: usize,
+                      spent: &str,
+                      dimension: &str)
+                      -> Option<usize> {
         match self.current_batch {
             Some(c) => {
-                let mut result = self.map
-                                     .entry(c)
-                                     .or_insert_with({
-                                         || Stat::new()
-                                     });;
-                result.done += 1;
+                let result = self.map
+                                 .entry(c)
+                                 .or_insert_with({
+                                     || Stat::new()
+                                 });
+                result.count_line(count);
                 result.last_time_stamp = time.to_owned();
+                result.record_delay(time, watermark, &self.options.buckets);
+                result.record_spent(spent);
+                result.record_dimension(dimension, count);
             }
             None => {
-                self.temp_count.done += 1;
+                self.temp_count.count_line(count);
                 self.temp_count.last_time_stamp = time.to_owned();
+                self.temp_count.record_delay(time, watermark, &self.options.buckets);
+                self.temp_count.record_spent(spent);
+                self.temp_count.record_dimension(dimension, count);
             }
         };
         Some(self.map.len())
@@ -162,21 +621,40 @@ impl ResultTrait for BatchResult {
         if let Some(batch) = self.current_batch {
             // the leftover_count from previous should be part of the last batch of this file
             // if batch is some, then add the count into batch.
-            let mut result = self.map
-                                 .entry(batch)
-                                 .or_insert_with({
-                                     || Stat::new()
-                                 });
+            let result = self.map
+                             .entry(batch)
+                             .or_insert_with({
+                                 || Stat::new()
+                             });
 
-            result.done += self.leftover_count.done;
-            if self.leftover_count.last_time_stamp != "" {
+            result.done = result.done.saturating_add(self.leftover_count.done);
+            result.lines += self.leftover_count.lines;
+            result.duplicates += self.leftover_count.duplicates;
+            result.sequence.merge(&self.leftover_count.sequence);
+            result.latency.merge(&self.leftover_count.latency);
+            result.unmatched_starts += self.leftover_count.unmatched_starts;
+            result.unmatched_ends += self.leftover_count.unmatched_ends;
+            result.delays.merge(&self.leftover_count.delays);
+            result.spent.merge(&self.leftover_count.spent);
+            result.dimensions.merge(&self.leftover_count.dimensions);
+            if !self.leftover_count.last_time_stamp.is_empty() {
                 result.last_time_stamp = self.leftover_count.last_time_stamp.clone();
             }
             // self.leftover_count = self.temp_count.clone();
             ::std::mem::swap(&mut self.leftover_count, &mut self.temp_count);
         } else {
-            self.leftover_count.done += self.temp_count.done;
-            if self.leftover_count.last_time_stamp == "" {
+            let done = self.leftover_count.done.saturating_add(self.temp_count.done);
+            self.leftover_count.done = done;
+            self.leftover_count.lines += self.temp_count.lines;
+            self.leftover_count.duplicates += self.temp_count.duplicates;
+            self.leftover_count.sequence.merge(&self.temp_count.sequence);
+            self.leftover_count.latency.merge(&self.temp_count.latency);
+            self.leftover_count.unmatched_starts += self.temp_count.unmatched_starts;
+            self.leftover_count.unmatched_ends += self.temp_count.unmatched_ends;
+            self.leftover_count.delays.merge(&self.temp_count.delays);
+            self.leftover_count.spent.merge(&self.temp_count.spent);
+            self.leftover_count.dimensions.merge(&self.temp_count.dimensions);
+            if self.leftover_count.last_time_stamp.is_empty() {
                 self.leftover_count.last_time_stamp = self.temp_count.last_time_stamp.clone();
             }
         }
@@ -185,17 +663,54 @@ impl ResultTrait for BatchResult {
         self.current_batch = None;
         self.map.len() + 1  // fake the length, batch better break the file loop as early as possible.
     }
-    fn print_result(&self, name: &str) {
-        // skip the first value, normally the record too old so likely to be incomplete.
-        for (count, key) in self.get_result().iter().rev().enumerate() {
-            match self.map.get(key) {
-                Some(val) => {
-                    println!("{}-{},{}", name, count, val.batch_to_str());
-                }
-                None => println!("{}-{},{}", name, count, "missing value"),
+    fn bucket_stat(&mut self, _: &str) -> Option<&mut Stat> {
+        Some(match self.current_batch {
+            Some(c) => self.map.entry(c).or_default(),
+            None => &mut self.temp_count,
+        })
+    }
+
+    fn set_options(&mut self, options: ResultOptions) {
+        self.options = options;
+    }
+
+    fn print_result(&self, name: &str, out: &mut Output) {
+        for (count, (key, val)) in self.map.iter().rev().enumerate() {
+            let row = Row {
+                name,
+                count,
+                key_name: "batch",
+                key: *key,
+                text: val.batch_to_str(),
+                stat: val,
+                efficiency: val.batch_efficiency(),
+                delay: None,
             };
+            report(out, row, &self.options);
+        }
+    }
+
+    fn counts(&self) -> Vec<(usize, u32, u32, u32)> {
+        self.map.iter().map(|(k, s)| (*k, s.total, s.done, s.lines)).collect()
+    }
+
+    fn stats(&self) -> Vec<(usize, &Stat)> {
+        self.map.iter().map(|(k, s)| (*k, s)).collect()
+    }
+
+    /// the lines before the first batch start of the stream belong to a batch started before,
+    /// they can't be reported so they are dropped.
+    fn prune(&mut self, keep: usize) {
+        keep_newest(&mut self.map, keep);
+        if self.current_batch.is_none() {
+            self.temp_count = Stat::new();
         }
     }
+
+    /// the lines go to the current batch, None if the batch start is not seen yet in this file
+    fn bucket(&self, _: &str) -> Option<usize> {
+        self.current_batch
+    }
 }
 
 #[cfg(test)]
@@ -205,70 +720,229 @@ mod tests {
     #[test]
     fn can_increase_hour_count() {
         let mut result = HourResult::new();
-        result.increase_count("2015-11-09 02:01:03", "2015-11-09 01:29:32", 1);
-        result.increase_count("2015-11-09 02:02:03", "2015-11-09 01:19:32", 1);
-        result.increase_count("2015-11-09 02:03:03", "2015-11-09 01:09:32", 1);
-        result.increase_count("2015-11-09 01:04", "2015-11-09 01:09:32", 1);
-        result.increase_count("2015-11-09 01:05", "2015-11-09 01:09:32", 1);
-        result.increase_count("nothing here", "test test", 1);
-        result.increase_count("nothing here", "", 1);
-        result.increase_count("", "", 1);
-        let c = result.increase_count("2015-11-09 01:06", "2015-11-09 01:09:32", 1);
+        result.increase_count("2015-11-09 02:01:03", "2015-11-09 01:29:32", 1, "", "");
+        result.increase_count("2015-11-09 02:02:03", "2015-11-09 01:19:32", 1, "", "");
+        result.increase_count("2015-11-09 02:03:03", "2015-11-09 01:09:32", 1, "", "");
+        result.increase_count("2015-11-09 01:04", "2015-11-09 01:09:32", 1, "", "");
+        result.increase_count("2015-11-09 01:05", "2015-11-09 01:09:32", 1, "", "");
+        result.increase_count("nothing here", "test test", 1, "", "");
+        result.increase_count("nothing here", "", 1, "", "");
+        result.increase_count("", "", 1, "", "");
+        let c = result.increase_count("2015-11-09 01:06", "2015-11-09 01:09:32", 1, "", "");
 
         // return value equals to the map length
-        assert_eq!(c.unwrap(), result.0.len());
+        assert_eq!(c.unwrap(), result.hours.len());
 
         verify_result_set(&result);
 
-        assert_eq!(result.0.get(&2015110902).unwrap().to_str(false),
+        assert_eq!(result.hours.get(&2015110902).unwrap().to_str(false),
                    "2015-11-09 02:03:03, 0, 3, 2015-11-09 01:09:32, 1.00, ");
 
-        assert_eq!(result.0.get(&2015110902).unwrap().to_str(true),
+        assert_eq!(result.hours.get(&2015110902).unwrap().to_str(true),
                    "2015-11-09 02:03:03, 0, 3, 2015-11-09 01:09:32, 1.00, 00:53:31");
 
-        assert_eq!(result.0.get(&2015110901).unwrap().to_str(false),
+        assert_eq!(result.hours.get(&2015110901).unwrap().to_str(false),
                    "2015-11-09 01:06, 0, 3, 2015-11-09 01:09:32, 0.50, ");
 
-        assert_eq!(result.0.get(&2015110901).unwrap().to_str(true),
+        assert_eq!(result.hours.get(&2015110901).unwrap().to_str(true),
                    "2015-11-09 01:06, 0, 3, 2015-11-09 01:09:32, 0.50, 0");
     }
 
     #[test]
     fn can_increase_trimmer_hour_count() {
         let mut result = HourResult::new();
-        result.increase_count("2015-11-09 02:01:03", "", 1);
-        result.increase_count("2015-11-09 02:02:03", "", 1);
-        result.increase_count("2015-11-09 02:03:03", "", 1);
-        result.increase_count("2015-11-09 01:04", "", 1);
-        result.increase_count("2015-11-09 01:05", "", 1);
-        let c = result.increase_count("2015-11-09 01:06", "", 1);
+        result.increase_count("2015-11-09 02:01:03", "", 1, "", "");
+        result.increase_count("2015-11-09 02:02:03", "", 1, "", "");
+        result.increase_count("2015-11-09 02:03:03", "", 1, "", "");
+        result.increase_count("2015-11-09 01:04", "", 1, "", "");
+        result.increase_count("2015-11-09 01:05", "", 1, "", "");
+        let c = result.increase_count("2015-11-09 01:06", "", 1, "", "");
 
         // return value equals to the map length
-        assert_eq!(c.unwrap(), result.0.len());
+        assert_eq!(c.unwrap(), result.hours.len());
 
         verify_result_set(&result);
 
-        assert_eq!(result.0.get(&2015110902).unwrap().to_str(false),
+        assert_eq!(result.hours.get(&2015110902).unwrap().to_str(false),
                    "2015-11-09 02:03:03, 0, 3, Not Available, 1.00, ");
 
-        assert_eq!(result.0.get(&2015110902).unwrap().to_str(true),
+        assert_eq!(result.hours.get(&2015110902).unwrap().to_str(true),
                    "2015-11-09 02:03:03, 0, 3, Not Available, 1.00, 0");
 
-        assert_eq!(result.0.get(&2015110901).unwrap().to_str(false),
+        assert_eq!(result.hours.get(&2015110901).unwrap().to_str(false),
                    "2015-11-09 01:06, 0, 3, Not Available, 0.50, ");
 
-        assert_eq!(result.0.get(&2015110901).unwrap().to_str(true),
+        assert_eq!(result.hours.get(&2015110901).unwrap().to_str(true),
                    "2015-11-09 01:06, 0, 3, Not Available, 0.50, 0");
     }
 
+    #[test]
+    fn can_collect_delay_distribution() {
+        let mut result = HourResult::new();
+        // big delays early in the hour, the last line is prompt
+        result.increase_count("2015-11-09 02:01:03", "2015-11-09 01:01:03", 1, "", "");
+        result.increase_count("2015-11-09 02:02:03", "2015-11-09 01:32:03", 1, "", "");
+        result.increase_count("2015-11-09 02:03:03", "", 1, "", "");
+        result.increase_count("2015-11-09 02:04:03", "2015-11-09 02:04:00", 1, "", "");
+
+        let stat = result.hours.get(&2015110902).unwrap();
+        assert_eq!(stat.delay_time(), "00:00:03");
+        assert_eq!(stat.delays.count(), 3);
+        // p50 is an estimate, 00:30:00 within 1%
+        assert_eq!(stat.delays.to_str(),
+                   "00:00:03, 01:00:00, 00:30:01, 00:29:50, 01:00:00, 01:00:00");
+        assert_eq!(Stat::new().delays.to_str(), ", , , , , ");
+    }
+
+    #[test]
+    fn can_find_stalls() {
+        let mut result = HourResult::new();
+        for time in &["2015-11-09 01:50:00", "2015-11-09 02:00:00", "2015-11-09 02:05:00",
+                      "2015-11-09 02:45:00", "2015-11-09 02:46:00", "2015-11-09 04:10:00"] {
+            result.increase_count(time, "", 1, "", "");
+        }
+        assert_eq!(result.hours.get(&2015110902).unwrap().minutes[45], 1);
+
+        let stalls = result.stalls(10 * 60);
+        assert_eq!(stalls.len(), 2);
+        // the whole 03 hour is missing
+        assert_eq!(minutes_to_str(stalls[0].start), "2015-11-09 02:47");
+        assert_eq!(minutes_to_str(stalls[0].end), "2015-11-09 04:10");
+        assert_eq!(minutes_to_str(stalls[1].start), "2015-11-09 02:06");
+        assert_eq!(stalls[1].seconds(), 39 * 60);
+
+        // 01:51 ... 01:59 are silent
+        assert_eq!(result.stalls(9 * 60).len(), 3);
+        assert_eq!(result.stalls(40 * 60).len(), 1);
+    }
+
+    #[test]
+    fn can_query_last_complete_hours() {
+        let mut result = HourResult::new();
+        for hour in &["2015-11-09 03:01", "2015-11-09 01:01", "2015-11-09 04:01", "2015-11-09 02:01"] {
+            result.increase_count(hour, "", 1, "", "");
+        }
+
+        let keys: Vec<_> = result.last_complete(2).iter().map(|r| r.0).collect();
+        assert_eq!(keys, [2015110904, 2015110903]);
+
+        // the oldest hour is never returned
+        let keys: Vec<_> = result.last_complete(10).iter().map(|r| r.0).collect();
+        assert_eq!(keys, [2015110904, 2015110903, 2015110902]);
+
+        assert_eq!(HourResult::new().last_complete(1).len(), 0);
+    }
+
+    #[test]
+    fn can_weight_counts() {
+        let mut result = HourResult::new();
+        result.increase_count("2015-11-09 02:01:03", "", 5, "", "");
+        result.increase_count("2015-11-09 02:03:03", "", 0, "", "");
+        result.increase_count("2015-11-09 02:05:03", "", 1, "", "");
+        assert_eq!(result.counts(), [(2015110902, 0, 6, 3)]);
+        // the efficiency is on the units, the minutes on the lines
+        let stat = result.hours.get(&2015110902).unwrap();
+        assert_eq!(stat.efficiency(), 1.2);
+        assert_eq!(stat.minutes[3], 1);
+
+        let mut result = BatchResult::new();
+        // before the batch start, carried to the batch of the next file
+        result.increase_count("2015-11-09 02:00:03", "", 2, "1s", "");
+        result.wrap_up_file();
+        result.process_batch("20151109020100", "", "10");
+        result.increase_count("2015-11-09 02:01:03", "", 3, "3s", "");
+        result.increase_count("2015-11-09 02:02:03", "", 4, "", "");
+        result.wrap_up_file();
+        assert_eq!(result.counts(), [(20151109020100, 10, 9, 3)]);
+        let spent = &result.map.get(&20151109020100).unwrap().spent;
+        assert_eq!((spent.count(), spent.min, spent.max), (2, 1000, 3000));
+    }
+
+    #[test]
+    fn can_skip_current_hour_in_baseline() {
+        let mut history = History::default();
+        for &hour in &[2015110202, 2015102602, 2015110203, 2015102603] {
+            history.insert("Hour", hour, Sample { done: 100, delay: None });
+        }
+        let mut result = HourResult::new();
+        result.set_options(ResultOptions {
+            anomaly: Some(AnomalyOptions {
+                history: ::std::sync::Arc::new(history),
+                weeks: 2,
+                threshold: 3.0,
+                method: ::history::Method::Mad,
+            }),
+            ..ResultOptions::default()
+        });
+        for time in &["2015-11-09 01:59:00", "2015-11-09 02:01:00", "2015-11-09 03:01:00"] {
+            result.increase_count(time, "", 1, "", "");
+        }
+
+        let mut out = Output::new(Format::Text);
+        result.print_result("Hour", &mut out);
+        let baselines: Vec<_> = out.lines.iter().filter(|l| l.contains("expected")).collect();
+        assert_eq!(baselines, ["Hour-1,baseline, done 1 expected 100-100 ANOMALY"]);
+
+        let mut saved = History::default();
+        result.record_history("Hour", &mut saved);
+        assert_eq!(saved.same_hour("Hour", 2015111602, 1).len(), 1);
+        assert!(saved.same_hour("Hour", 2015111603, 1).is_empty());
+    }
+
+    #[test]
+    fn can_show_optional_columns() {
+        let mut result = HourResult::new();
+        result.increase_count("2015-11-09 01:59:03", "", 1, "", "");
+        result.increase_count("2015-11-09 02:01:03", "", 3, "250", "");
+        let mut out = Output::new(Format::Text);
+        result.print_result("Hour", &mut out);
+        assert_eq!(out.lines, ["Hour-0,2015-11-09 02:01:03, 0, 3, Not Available, 3.00, 0"]);
+        assert_eq!(Columns::default().header(), TEXT_HEADER);
+
+        let columns = Columns { lines: true, spent: true, ..Columns::default() };
+        result.set_options(ResultOptions { columns, ..ResultOptions::default() });
+        let mut out = Output::new(Format::Text);
+        result.print_result("Hour", &mut out);
+        assert_eq!(out.lines,
+                   ["Hour-0,2015-11-09 02:01:03, 0, 3, Not Available, 3.00, 0, 1, 250, 250, 250, \
+                     250, 250, 250"]);
+        assert_eq!(columns.header().split(", ").count(), 14);
+        assert!(columns.header().ends_with("Delay, Lines, SpentMin(ms), SpentMax(ms), \
+                                            SpentMean(ms), SpentP50(ms), SpentP90(ms), \
+                                            SpentP99(ms)"));
+    }
+
+    #[test]
+    fn can_report_top_dimensions() {
+        let mut result = HourResult::new();
+        result.set_options(ResultOptions { top: 2, ..ResultOptions::default() });
+        result.increase_count("2015-11-09 01:59:03", "", 1, "", "a");
+        result.increase_count("2015-11-09 02:01:03", "", 5, "", "a");
+        result.increase_count("2015-11-09 02:02:03", "", 3, "", "b");
+        result.increase_count("2015-11-09 02:03:03", "", 2, "", "c");
+        result.increase_count("2015-11-09 02:04:03", "", 4, "", "b");
+        result.increase_count("2015-11-09 02:05:03", "", 1, "", "");
+
+        let mut out = Output::new(Format::Text);
+        result.print_result("Hour", &mut out);
+        assert_eq!(out.lines.len(), 2);
+        assert_eq!(out.lines[1], "Hour-0,top, b 7, a 5, other 2");
+
+        let mut out = Output::new(Format::Json);
+        result.print_result("Hour", &mut out);
+        assert_eq!(out.lines[1],
+                   concat!(r#"{"name":"Hour","index":0,"top":[{"value":"b","count":7,"error":0},"#,
+                           r#"{"value":"a","count":5,"error":0}],"other":2}"#));
+    }
+
     fn verify_result_set(result: &HourResult) {
 
-        for (_, val) in &result.0 {
+        for val in result.hours.values() {
             // logs can be porperly categoried in map
             assert_eq!(3, val.done);
         }
 
-        // let keys: Vec<_> = result.0.keys().into_iter().cloned().collect();
+        // let keys: Vec<_> = result.hours.keys().into_iter().cloned().collect();
 
         // keys are in order
         // assert_eq!(keys, [2015110901, 2015110902]);
@@ -1,6 +1,18 @@
+use std::convert::TryFrom;
 use std::fmt;
-use time::{Duration, strptime, Tm};
+use time::{self, Duration, strptime, Timespec, Tm};
 use error::*;
+use sketch::QuantileSketch;
+use topk::TopK;
+use sequence::SequenceStat;
+use output::{Output, json_str, json_opt};
+
+/// Default upper bounds of the delay histogram buckets, in seconds:
+/// 0-1s, 1-5s, 5-30s, 30s-1m, 1-5m, >5m
+pub const DEFAULT_BUCKETS: [i64; 5] = [1, 5, 30, 60, 300];
+
+/// Width of the bars in the text histogram
+const BAR_WIDTH: u32 = 30;
 
 #[derive(Debug,Clone, Default)]
 pub struct Stat {
@@ -11,10 +23,30 @@ pub struct Stat {
     pub last_sample_time: String,
     /// batch size, for hour stat this should be 0
     pub total: u32,
-    /// how many works done for this hour
+    /// how many works done for this hour, the sum of the counts of the lines
     pub done: u32,
+    /// how many lines matched for this hour
+    pub lines: u32,
+    /// how many lines were dropped, their id was already seen within the dedup window
+    pub duplicates: u32,
+    /// gaps, out of order and resets of the seq capture
+    pub sequence: SequenceStat,
+    /// latencies between the start and the end lines of the messages, in seconds
+    pub latency: DelayStat,
+    /// start lines without end line, in flight or lost
+    pub unmatched_starts: u32,
+    /// end lines without start line
+    pub unmatched_ends: u32,
     /// should be the last msg DB write time for this hour
     pub last_time_stamp: String,
+    /// delays of every line with a watermark in this hour/batch
+    pub delays: DelayStat,
+    /// processing times of every line with a spent capture in this hour/batch, in milliseconds
+    pub spent: DelayStat,
+    /// works done by the value of the dimension capture, only the most counted values are kept
+    pub dimensions: TopK,
+    /// for hour stat, count of every minute of the hour. empty for batch stat
+    pub minutes: Vec<u32>,
 }
 
 impl Stat {
@@ -29,16 +61,51 @@ impl Stat {
         let time_stamp = self.last_time_stamp.parse::<LogTime>();
 
         match (sample_time, time_stamp) {
-            (Ok(s), Ok(t)) => {
-                let delay = s - t;
-                format!("{:02}:{:02}:{:02}",
-                        delay.num_hours(),
-                        delay.num_minutes() % 60,
-                        delay.num_seconds() % 60)
-            }
+            (Ok(s), Ok(t)) => hms((s - t).num_seconds()),
             _ => "0".to_owned(),
         }
     }
+
+    /// count_line adds a matched line standing for `count` works, the works saturate at
+    /// `u32::MAX` instead of wrapping around
+    pub fn count_line(&mut self, count: usize) {
+        self.lines += 1;
+        self.done = self.done.saturating_add(u32::try_from(count).unwrap_or(u32::MAX));
+    }
+
+    /// record_delay adds the delay between the log time and the watermark of a single line.
+    /// lines without a valid watermark are ignored.
+    /// buckets are the upper bounds of the delay histogram, empty if histogram is not required.
+    pub fn record_delay(&mut self, time: &str, watermark: &str, buckets: &[i64]) {
+        if let (Ok(s), Ok(t)) = (time.parse::<LogTime>(), watermark.parse::<LogTime>()) {
+            self.delays.record((s - t).num_seconds(), buckets);
+        }
+    }
+
+    /// record_spent adds the processing time of a single line, see `parse_millis`.
+    /// lines without a valid processing time are ignored.
+    pub fn record_spent(&mut self, spent: &str) {
+        if let Ok(millis) = parse_millis(spent) {
+            self.spent.record(millis, &[]);
+        }
+    }
+
+    /// record_dimension adds the works of a single line to its dimension value, lines without
+    /// one are ignored.
+    pub fn record_dimension(&mut self, dimension: &str, count: usize) {
+        if !dimension.is_empty() {
+            self.dimensions.insert(dimension, count as u64);
+        }
+    }
+
+    /// efficiency of a hour stat, works done per minute
+    pub fn efficiency(&self) -> f32 {
+        let duration = match self.duration {
+            0 => 1,
+            n => n,
+        };
+        self.done as f32 / duration as f32
+    }
     /// to_str is a helper function to convert Stat into String.
     /// follow the format "duration, last sample time stamp, total, done, last msg time stamp, eff, delay"
     /// *** Paramter ***
@@ -47,11 +114,6 @@ impl Stat {
     /// information
     pub fn to_str(&self, delay: bool) -> String {
 
-        let duration = match self.duration {
-            0 => 1,
-            n => n,
-        };
-
         // "duration, last sample time stamp, total, done, last msg time stamp, eff, delay"
         format!("{}, {}, {}, {}, {:.2}, {}",
                 self.last_sample_time,
@@ -61,7 +123,7 @@ impl Stat {
                     Ok(e) => e.to_string(),
                     Err(e) => e.to_string(),
                 },
-                (self.done as f32 / duration as f32),
+                self.efficiency(),
                 if delay {
                     self.delay_time()
                 } else {
@@ -69,14 +131,15 @@ impl Stat {
                 })
     }
 
-    fn cal_batch_eff(&self) -> f32 {
+    /// efficiency of a batch stat, done per minute since the batch start time
+    pub fn batch_efficiency(&self) -> f32 {
         let sample_time = self.last_sample_time.parse::<LogTime>();
         let time_stamp = self.last_time_stamp.parse::<LogTime>();
 
         match (sample_time, time_stamp) {
             (Ok(s), Ok(t)) => {
                 let time = (t - s).num_seconds() as f32 / 60.0;
-                (self.done as f32 / time)
+                self.done as f32 / time
             }
             _ => 0.0,
         }
@@ -93,9 +156,421 @@ impl Stat {
                     Ok(e) => e.to_string(),
                     Err(e) => e.to_string(),
                 },
-                self.cal_batch_eff(),
+                self.batch_efficiency(),
                 "")
     }
+
+    /// to_json converts Stat into a single line JSON object, same content as to_str plus the
+    /// delay distribution. Delays are in seconds.
+    /// delay is the last sample delay, only for hour stat.
+    pub fn to_json(&self,
+                   name: &str,
+                   index: usize,
+                   efficiency: f32,
+                   delay: Option<String>,
+                   buckets: &[i64])
+                   -> String {
+        let histogram = if buckets.is_empty() {
+            String::new()
+        } else {
+            let counts: Vec<_> = self.delays
+                                     .histogram(buckets)
+                                     .iter()
+                                     .enumerate()
+                                     .map(|(i, count)| {
+                                         format!(r#"{{"bucket":{},"le":{},"count":{}}}"#,
+                                                 json_str(&bucket_label(buckets, i)),
+                                                 json_opt(buckets.get(i)),
+                                                 count)
+                                     })
+                                     .collect();
+            format!(r#","histogram":[{}]"#, counts.join(","))
+        };
+        let minutes = if self.minutes.is_empty() {
+            String::new()
+        } else {
+            let counts: Vec<_> = self.minutes.iter().map(|c| c.to_string()).collect();
+            format!(r#","minutes":[{}]"#, counts.join(","))
+        };
+
+        format!(concat!(r#"{{"name":{},"index":{},"last_sample_time":{},"total":{},"done":{},"#,
+                        r#""lines":{},"duplicates":{},"#,
+                        r#""last_time_stamp":{},"efficiency":{:.2},"delay":{},"#,
+                        r#""delays":{},"spent":{},"sequence":{},"latency":{},"#,
+                        r#""unmatched_starts":{},"unmatched_ends":{}{}{}}}"#),
+                json_str(name),
+                index,
+                json_str(&self.last_sample_time),
+                self.total,
+                self.done,
+                self.lines,
+                self.duplicates,
+                self.last_time_stamp
+                    .parse::<LogTime>()
+                    .ok()
+                    .map_or("null".to_owned(), |t| json_str(&t.to_string())),
+                efficiency,
+                delay.map_or("null".to_owned(), |t| json_str(&t)),
+                self.delays.to_json(),
+                self.spent.to_json(),
+                self.sequence.to_json(),
+                self.latency.to_json(),
+                self.unmatched_starts,
+                self.unmatched_ends,
+                histogram,
+                minutes)
+    }
+
+    /// to_metrics adds the stat into Prometheus output, labeled by the monitor name and the
+    /// hour/batch key.
+    pub fn to_metrics(&self,
+                      out: &mut Output,
+                      labels: &[(&str, &str)],
+                      efficiency: f32,
+                      buckets: &[i64]) {
+        out.metric("tc_total", "gauge", "", labels, f64::from(self.total));
+        out.metric("tc_done", "gauge", "", labels, f64::from(self.done));
+        out.metric("tc_lines", "gauge", "", labels, f64::from(self.lines));
+        out.metric("tc_duplicates", "gauge", "", labels, f64::from(self.duplicates));
+        let s = &self.sequence;
+        out.metric("tc_sequence_gaps", "gauge", "", labels, f64::from(s.gaps));
+        out.metric("tc_sequence_missing", "gauge", "", labels, s.missing as f64);
+        out.metric("tc_sequence_out_of_order", "gauge", "", labels, f64::from(s.out_of_order));
+        out.metric("tc_sequence_resets", "gauge", "", labels, f64::from(s.resets));
+
+        let l = &self.latency;
+        if l.count() > 0 {
+            for q in &["0.5", "0.9", "0.99"] {
+                let value = l.quantile(q.parse().unwrap()).unwrap_or(0);
+                let mut ql = labels.to_vec();
+                ql.push(("quantile", q));
+                out.metric("tc_latency_seconds", "summary", "", &ql, value as f64);
+            }
+            out.metric("tc_latency_seconds", "summary", "_sum", labels, l.sum as f64);
+            out.metric("tc_latency_seconds", "summary", "_count", labels, f64::from(l.count()));
+        }
+        out.metric("tc_unmatched_starts", "gauge", "", labels, f64::from(self.unmatched_starts));
+        out.metric("tc_unmatched_ends", "gauge", "", labels, f64::from(self.unmatched_ends));
+        out.metric("tc_efficiency", "gauge", "", labels, f64::from(efficiency));
+
+        let d = &self.delays;
+        if d.count() > 0 {
+            out.metric("tc_delay_min_seconds", "gauge", "", labels, d.min as f64);
+            out.metric("tc_delay_max_seconds", "gauge", "", labels, d.max as f64);
+            for q in &["0.5", "0.9", "0.99"] {
+                let value = d.quantile(q.parse().unwrap()).unwrap_or(0);
+                let mut l = labels.to_vec();
+                l.push(("quantile", q));
+                out.metric("tc_delay_seconds", "summary", "", &l, value as f64);
+            }
+        }
+        out.metric("tc_delay_seconds", "summary", "_sum", labels, d.sum as f64);
+        out.metric("tc_delay_seconds", "summary", "_count", labels, f64::from(d.count()));
+
+        let s = &self.spent;
+        if s.count() > 0 {
+            for q in &["0.5", "0.9", "0.99"] {
+                let value = s.quantile(q.parse().unwrap()).unwrap_or(0);
+                let mut l = labels.to_vec();
+                l.push(("quantile", q));
+                out.metric("tc_spent_seconds", "summary", "", &l, value as f64 / 1000.0);
+            }
+            out.metric("tc_spent_seconds", "summary", "_sum", labels, s.sum as f64 / 1000.0);
+            out.metric("tc_spent_seconds", "summary", "_count", labels, f64::from(s.count()));
+        }
+
+        if !buckets.is_empty() {
+            let mut cumulative = 0;
+            for (i, count) in d.histogram(buckets).iter().enumerate() {
+                cumulative += *count;
+                let le = buckets.get(i).map_or("+Inf".to_owned(), |b| b.to_string());
+                let mut l = labels.to_vec();
+                l.push(("le", &le));
+                out.metric("tc_delay_histogram_seconds",
+                           "histogram",
+                           "_bucket",
+                           &l,
+                           f64::from(cumulative));
+            }
+            out.metric("tc_delay_histogram_seconds",
+                       "histogram",
+                       "_sum",
+                       labels,
+                       d.sum as f64);
+            out.metric("tc_delay_histogram_seconds",
+                       "histogram",
+                       "_count",
+                       labels,
+                       f64::from(d.count()));
+        }
+    }
+}
+
+/// parse_seconds converts a duration like "90", "30s", "5m" or "1h" into seconds.
+pub fn parse_seconds(s: &str) -> Result<i64> {
+    let s = s.trim();
+    let (number, unit) = match s.char_indices().find(|&(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => (&s[..i], &s[i..]),
+        None => (s, "s"),
+    };
+    let number = number.parse::<i64>().map_err(|_| LogError::InvalidDuration)?;
+    match unit {
+        "s" => Ok(number),
+        "m" => Ok(number * 60),
+        "h" => Ok(number * 3600),
+        _ => Err(LogError::InvalidDuration),
+    }
+}
+
+/// parse_millis converts a processing time into milliseconds, for example "153ms", "1.5s", "2m",
+/// "PT1.5S" (ISO 8601) or "00:00:01.250". A number without unit is in milliseconds.
+pub fn parse_millis(s: &str) -> Result<i64> {
+    let s = s.trim();
+    let seconds = if s.starts_with("PT") || s.starts_with("pt") {
+        iso_seconds(&s[2..])
+    } else if s.contains(':') {
+        clock_seconds(s)
+    } else {
+        let i = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+        s[..i].parse::<f64>().ok().and_then(|n| {
+            match &s[i..] {
+                "" | "ms" => Some(n / 1000.0),
+                "us" | "µs" => Some(n / 1_000_000.0),
+                "s" | "sec" => Some(n),
+                "m" | "min" => Some(n * 60.0),
+                "h" => Some(n * 3600.0),
+                _ => None,
+            }
+        })
+    };
+    seconds.map(|s| (s * 1000.0).round() as i64).ok_or(LogError::InvalidDuration)
+}
+
+/// the time part of an ISO 8601 duration, for example "1H2M3.5S"
+fn iso_seconds(s: &str) -> Option<f64> {
+    if s.is_empty() {
+        return None;
+    }
+    let mut seconds = 0.0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let i = rest.find(|c: char| c.is_ascii_alphabetic())?;
+        let number = rest[..i].parse::<f64>().ok()?;
+        seconds += number *
+                   match rest.as_bytes()[i].to_ascii_uppercase() {
+                       b'H' => 3600.0,
+                       b'M' => 60.0,
+                       b'S' => 1.0,
+                       _ => return None,
+                   };
+        rest = &rest[i + 1..];
+    }
+    Some(seconds)
+}
+
+/// "HH:MM:SS.fff" or "MM:SS.fff"
+fn clock_seconds(s: &str) -> Option<f64> {
+    let parts = s.split(':').map(|p| p.parse::<f64>().ok()).collect::<Option<Vec<_>>>()?;
+    match parts.len() {
+        2 | 3 => Some(parts.iter().fold(0.0, |seconds, p| seconds * 60.0 + p)),
+        _ => None,
+    }
+}
+
+/// parse_buckets converts a comma separated list of durations into histogram bucket bounds,
+/// for example "1s,5s,30s,1m,5m".
+pub fn parse_buckets(s: &str) -> Result<Vec<i64>> {
+    let buckets = s.split(',').map(parse_seconds).collect::<Result<Vec<_>>>()?;
+    if buckets.is_empty() || buckets.windows(2).any(|w| w[0] >= w[1]) {
+        return Err(LogError::InvalidDuration);
+    }
+    Ok(buckets)
+}
+
+/// short_duration formats seconds with the biggest unit which fits, for example "30s", "5m".
+fn short_duration(seconds: i64) -> (i64, &'static str) {
+    match seconds {
+        0 => (0, ""),
+        s if s % 3600 == 0 => (s / 3600, "h"),
+        s if s % 60 == 0 => (s / 60, "m"),
+        s => (s, "s"),
+    }
+}
+
+/// bucket_label names the histogram bucket `index`, for example "0-1s", "30s-1m" and ">5m".
+pub fn bucket_label(buckets: &[i64], index: usize) -> String {
+    let lower = if index == 0 { 0 } else { buckets[index - 1] };
+    let (low, low_unit) = short_duration(lower);
+    match buckets.get(index) {
+        Some(upper) => {
+            let (up, up_unit) = short_duration(*upper);
+            if low_unit == up_unit || low == 0 {
+                format!("{}-{}{}", low, up, up_unit)
+            } else {
+                format!("{}{}-{}{}", low, low_unit, up, up_unit)
+            }
+        }
+        None => format!(">{}{}", low, low_unit),
+    }
+}
+
+/// formats seconds as "HH:MM:SS"
+pub fn hms(seconds: i64) -> String {
+    format!("{:02}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60)
+}
+
+/// `DelayStat` is the delay distribution of a hour or batch, in seconds. Quantiles come from a
+/// `QuantileSketch` so the memory stays bounded whatever the number of lines.
+#[derive(Debug, Clone, Default)]
+pub struct DelayStat {
+    pub min: i64,
+    pub max: i64,
+    pub sum: i64,
+    sketch: QuantileSketch,
+    /// counts of the delay histogram buckets, only kept if buckets are given.
+    histogram: Vec<u32>,
+}
+
+impl DelayStat {
+    /// a negative delay means the clocks are not in sync, count it as no delay.
+    pub fn record(&mut self, seconds: i64, buckets: &[i64]) {
+        let seconds = seconds.max(0);
+        if self.count() == 0 || seconds < self.min {
+            self.min = seconds;
+        }
+        if seconds > self.max {
+            self.max = seconds;
+        }
+        self.sum += seconds;
+        self.sketch.insert(seconds as f64);
+
+        if !buckets.is_empty() {
+            let index = buckets.iter().position(|b| seconds < *b).unwrap_or(buckets.len());
+            self.histogram.resize(buckets.len() + 1, 0);
+            self.histogram[index] += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: &DelayStat) {
+        if other.count() == 0 {
+            return;
+        }
+        if self.count() == 0 || other.min < self.min {
+            self.min = other.min;
+        }
+        if other.max > self.max {
+            self.max = other.max;
+        }
+        self.sum += other.sum;
+        self.sketch.merge(&other.sketch);
+
+        if self.histogram.len() < other.histogram.len() {
+            self.histogram.resize(other.histogram.len(), 0);
+        }
+        for (count, other) in self.histogram.iter_mut().zip(&other.histogram) {
+            *count += *other;
+        }
+    }
+
+    /// Returns the count of every histogram bucket, the last one counts the delays above the
+    /// last bound.
+    pub fn histogram(&self, buckets: &[i64]) -> Vec<u32> {
+        let mut counts = self.histogram.clone();
+        counts.resize(buckets.len() + 1, 0);
+        counts
+    }
+
+    /// histogram_chart draws the histogram as a bar chart, one line per bucket.
+    pub fn histogram_chart(&self, buckets: &[i64]) -> String {
+        let counts = self.histogram(buckets);
+        let highest = counts.iter().cloned().max().unwrap_or(0).max(1);
+        let lines: Vec<_> = counts.iter()
+                                  .enumerate()
+                                  .map(|(i, count)| {
+                                      let bar = (count * BAR_WIDTH).div_ceil(highest);
+                                      format!("    {:>7} |{:<width$}| {}",
+                                              bucket_label(buckets, i),
+                                              "#".repeat(bar as usize),
+                                              count,
+                                              width = BAR_WIDTH as usize)
+                                  })
+                                  .collect();
+        lines.join("\n")
+    }
+
+    pub fn count(&self) -> u32 {
+        self.sketch.count()
+    }
+
+    pub fn mean(&self) -> Option<i64> {
+        match self.count() {
+            0 => None,
+            n => Some(self.sum / i64::from(n)),
+        }
+    }
+
+    /// the estimate is within 1% of the real delay, but never outside the min/max seen.
+    pub fn quantile(&self, q: f64) -> Option<i64> {
+        self.sketch.quantile(q).map(|v| (v.round() as i64).clamp(self.min, self.max))
+    }
+
+    /// follow the format "min, max, mean, p50, p90, p99", all in "HH:MM:SS".
+    /// every column is empty if there is no watermark at all.
+    pub fn to_str(&self) -> String {
+        self.format(hms)
+    }
+
+    /// same as `to_str`, the values are formatted by `unit`
+    pub fn format(&self, unit: fn(i64) -> String) -> String {
+        if self.count() == 0 {
+            return ", , , , , ".to_owned();
+        }
+        let values = [Some(self.min),
+                      Some(self.max),
+                      self.mean(),
+                      self.quantile(0.5),
+                      self.quantile(0.9),
+                      self.quantile(0.99)];
+        values.iter().map(|v| v.map_or(String::new(), unit)).collect::<Vec<_>>().join(", ")
+    }
+
+    /// to_json converts the distribution into a JSON object, min and max are null if empty.
+    pub fn to_json(&self) -> String {
+        let has_value = self.count() > 0;
+        format!(r#"{{"count":{},"min":{},"max":{},"mean":{},"p50":{},"p90":{},"p99":{}}}"#,
+                self.count(),
+                json_opt(if has_value { Some(self.min) } else { None }),
+                json_opt(if has_value { Some(self.max) } else { None }),
+                json_opt(self.mean()),
+                json_opt(self.quantile(0.5)),
+                json_opt(self.quantile(0.9)),
+                json_opt(self.quantile(0.99)))
+    }
+}
+
+/// hour_minutes converts the hour index (for example 2015110902) into minutes since epoch.
+pub fn hour_minutes(hour: usize) -> Option<i64> {
+    strptime(&hour.to_string(), "%Y%m%d%H").ok().map(|t| t.to_timespec().sec / 60)
+}
+
+/// minutes_to_hour converts minutes since epoch into the hour index, the reverse of
+/// `hour_minutes`
+pub fn minutes_to_hour(minutes: i64) -> Option<usize> {
+    time::at_utc(Timespec::new(minutes * 60, 0))
+        .strftime("%Y%m%d%H")
+        .ok()
+        .and_then(|t| t.to_string().parse().ok())
+}
+
+/// formats minutes since epoch as "%Y-%m-%d %H:%M"
+pub fn minutes_to_str(minutes: i64) -> String {
+    match time::at_utc(Timespec::new(minutes * 60, 0)).strftime("%Y-%m-%d %H:%M") {
+        Ok(t) => t.to_string(),
+        Err(e) => e.to_string(),
+    }
 }
 
 /// `LogTime` is for date time format conversion and help to calculates delta, for example to calculate
@@ -147,6 +622,13 @@ impl ::std::str::FromStr for LogTime {
     }
 }
 
+impl LogTime {
+    /// seconds since epoch, the time zone is ignored
+    pub fn seconds(&self) -> i64 {
+        self.0.to_timespec().sec
+    }
+}
+
 impl fmt::Display for LogTime {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.0.strftime("%Y-%m-%d %H:%M:%S") {
@@ -186,4 +668,76 @@ mod tests {
             Err(e) => assert_eq!(e.to_string(), "Not Available"),
         }
     }
+
+    #[test]
+    fn can_saturate_done() {
+        let mut stat = Stat::new();
+        stat.count_line(3);
+        stat.count_line(usize::MAX);
+        stat.count_line(1);
+        assert_eq!((stat.lines, stat.done), (3, u32::MAX));
+    }
+
+    #[test]
+    fn can_parse_buckets() {
+        assert_eq!(parse_buckets("1s,5s,30s,1m,5m").unwrap(), DEFAULT_BUCKETS);
+        assert_eq!(parse_buckets("10,2h").unwrap(), [10, 7200]);
+        assert!(parse_buckets("5m,1m").is_err());
+        assert!(parse_buckets("1d").is_err());
+        assert!(parse_buckets("").is_err());
+
+        let labels: Vec<_> = (0..6).map(|i| bucket_label(&DEFAULT_BUCKETS, i)).collect();
+        assert_eq!(labels, ["0-1s", "1-5s", "5-30s", "30s-1m", "1-5m", ">5m"]);
+    }
+
+    #[test]
+    fn can_parse_millis() {
+        assert_eq!(parse_millis("153ms").unwrap(), 153);
+        assert_eq!(parse_millis("153").unwrap(), 153);
+        assert_eq!(parse_millis("1.5s").unwrap(), 1500);
+        assert_eq!(parse_millis("2m").unwrap(), 120_000);
+        assert_eq!(parse_millis("250us").unwrap(), 0);
+        assert_eq!(parse_millis("PT1.5S").unwrap(), 1500);
+        assert_eq!(parse_millis("PT1M2S").unwrap(), 62_000);
+        assert_eq!(parse_millis("00:00:01.250").unwrap(), 1250);
+        assert_eq!(parse_millis("01:02.5").unwrap(), 62_500);
+        assert!(parse_millis("PT").is_err());
+        assert!(parse_millis("1.5d").is_err());
+        assert!(parse_millis("-5ms").is_err());
+        assert!(parse_millis("").is_err());
+    }
+
+    #[test]
+    fn can_collect_spent() {
+        let mut stat = Stat::new();
+        for spent in &["100ms", "0.2s", "PT0.3S", "bad"] {
+            stat.record_spent(spent);
+        }
+        assert_eq!(stat.spent.count(), 3);
+        // p50 is an estimate, 200 within 1%
+        assert_eq!(stat.spent.format(|v| v.to_string()), "100, 300, 200, 198, 300, 300");
+        assert!(stat.to_json("Hour", 0, 1.0, None, &[])
+                    .contains(r#""spent":{"count":3,"min":100,"max":300,"mean":200,"#));
+    }
+
+    #[test]
+    fn can_count_histogram() {
+        let mut stat = Stat::new();
+        for time in &["2015-11-09 02:00:00", "2015-11-09 02:00:03", "2015-11-09 02:10:00"] {
+            stat.record_delay(time, "2015-11-09 02:00:00", &DEFAULT_BUCKETS);
+        }
+        assert_eq!(stat.delays.histogram(&DEFAULT_BUCKETS), [1, 1, 0, 0, 0, 1]);
+
+        let mut stat = Stat::new();
+        for time in &["2015-11-09 02:00:00", "2015-11-09 02:00:03", "2015-11-09 02:10:00"] {
+            stat.record_delay(time, "2015-11-09 02:00:00", &[5]);
+        }
+        assert_eq!(stat.delays.histogram_chart(&[5]),
+                   concat!("       0-5s |##############################| 2\n",
+                           "        >5s |###############               | 1"));
+
+        let json = stat.to_json("Hour", 0, 1.0, None, &[5]);
+        assert!(json.contains(r#""delays":{"count":3,"min":0,"max":600,"mean":201,"#));
+        assert!(json.ends_with(r#""histogram":[{"bucket":"0-5s","le":5,"count":2},{"bucket":">5s","le":null,"count":1}]}"#));
+    }
 }
\ No newline at end of file
@@ -1,10 +1,11 @@
-#![feature(slice_patterns, test)]
+#![feature(test)]
 
 extern crate regex;
 extern crate glob;
 extern crate time;
 extern crate clap;
 extern crate fnv;
+extern crate aho_corasick;
 extern crate test;
 
 mod tc;
@@ -12,10 +13,136 @@ mod logresult;
 mod logparser;
 mod error;
 mod logstat;
+mod inthash;
+mod sketch;
+mod output;
+mod history;
+mod input;
+mod rotation;
+mod explain;
+mod patterntest;
+mod fields;
+mod filter;
+mod topk;
+mod dedup;
+mod sequence;
+mod pairing;
+mod pipeline;
+mod template;
+mod config;
+mod source;
+mod listener;
 
-use clap::{Arg, App};
-use std::thread;
+use clap::{Arg, App, ArgMatches, SubCommand};
+use regex::Regex;
+use std::fs::{self, read_to_string, File};
+use std::io::{self, BufRead, BufReader};
+use std::process;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, Instant};
 use tc::*;
+use logresult::{Columns, ResultOptions};
+use logstat::{parse_buckets, parse_seconds, DEFAULT_BUCKETS};
+use output::{Format, Output};
+use history::{AnomalyOptions, History, Method};
+use input::InputOptions;
+use explain::Explain;
+use logparser::LogParser;
+use patterntest::{diff, test_pattern};
+use fields::{FieldFormat, FieldMatcher};
+use filter::Filter;
+use pipeline::Pipeline;
+use source::{Remote, Source};
+use listener::Listen;
+use glob::Pattern;
+
+fn filter(matches: &ArgMatches) -> Filter {
+    matches.value_of("filter").unwrap().parse().unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// pipelines parses the pipelines, both monitors of every pipeline must count by hour.
+fn pipelines(monitors: &[TcTool], matches: &ArgMatches) -> Vec<Pipeline> {
+    matches.values_of("pipeline")
+           .into_iter()
+           .flatten()
+           .map(|pipeline| {
+               let pipeline = pipeline.parse::<Pipeline>().expect("Invalid pipeline");
+               pipeline.validate(monitors).unwrap_or_else(|e| panic!("{}", e));
+               pipeline
+           })
+           .collect()
+}
+
+/// report adds the result of the monitors and the pipelines into a new output.
+fn report(monitors: &[TcTool],
+          pipelines: &[Pipeline],
+          columns: &Columns,
+          format: Format)
+          -> Output {
+    let mut out = Output::new(format);
+    out.header = columns.header();
+    for tc in monitors {
+        tc.print_result(&mut out);
+    }
+    for pipeline in pipelines {
+        let stats = |name: &str| {
+            monitors.iter().find(|tc| tc.name() == name).expect("Unknown monitor").stats()
+        };
+        let hours = pipeline.hours(&stats(&pipeline.producer), &stats(&pipeline.consumer));
+        pipeline.report(&hours, &mut out);
+    }
+    out
+}
+
+/// listen feeds the lines received to the monitors until the listeners stop, the result is
+/// shown, or written into the snapshot file, every interval.
+fn listen(monitors: Vec<TcTool>,
+          pipelines: &[Pipeline],
+          columns: &Columns,
+          matches: &ArgMatches,
+          format: Format) {
+    let routes: Vec<_> = matches.values_of("route").map_or(Vec::new(), |r| r.collect());
+    let mut router = Router::new(monitors, &routes).unwrap_or_else(|e| panic!("{}", e));
+    let interval = matches.value_of("interval").map_or(Ok(60), parse_seconds);
+    let interval = match interval {
+        Ok(seconds) if seconds > 0 => Duration::from_secs(seconds as u64),
+        _ => panic!("Invalid interval, expects at least 1s"),
+    };
+    let (sender, receiver) = channel();
+    for listen in matches.values_of("listen").unwrap() {
+        let address = listen.parse::<Listen>()
+                            .unwrap_or_else(|e| panic!("{}", e))
+                            .start(sender.clone())
+                            .expect("Failed to listen");
+        eprintln!("listening on {}", address);
+    }
+    drop(sender);
+
+    let mut next = Instant::now() + interval;
+    loop {
+        match receiver.recv_timeout(next.saturating_duration_since(Instant::now())) {
+            Ok(message) => router.process(&message),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        if Instant::now() >= next {
+            router.wrap_up_interval();
+            let out = report(router.monitors(), pipelines, columns, format);
+            match matches.value_of("snapshot") {
+                Some(path) => {
+                    // replaced at once, so the readers never see a partial file
+                    let temp = format!("{}.tmp", path);
+                    fs::write(&temp, out.render())
+                        .and_then(|_| fs::rename(&temp, path))
+                        .expect("Failed to write snapshot file.");
+                }
+                None => out.print(),
+            }
+            next = Instant::now() + interval;
+        }
+    }
+}
 
 fn main() {
 
@@ -26,39 +153,480 @@ fn main() {
                       .arg(Arg::with_name("CONFIG")
                                .short("t")
                                .long("tc")
-                               .help("Sets a log type")
+                               .help("Sets the monitors: hour, the name of a template, for \
+                                      example NgConsumer, or a config file")
                                .takes_value(true))
+                      .arg(Arg::with_name("INPUT")
+                               .help("Log files, directories or glob patterns to read instead \
+                                      of the files of the monitors, \"-\" for stdin")
+                               .multiple(true)
+                               .index(1))
+                      .arg(Arg::with_name("recursive")
+                               .short("r")
+                               .long("recursive")
+                               .help("Also reads the files in the sub directories of a directory \
+                                      input"))
+                      .arg(Arg::with_name("include")
+                               .long("include")
+                               .help("Only reads the files whose name matches the glob pattern")
+                               .multiple(true)
+                               .number_of_values(1)
+                               .takes_value(true))
+                      .arg(Arg::with_name("exclude")
+                               .long("exclude")
+                               .help("Skips the files whose name matches the glob pattern")
+                               .multiple(true)
+                               .number_of_values(1)
+                               .takes_value(true))
+                      .arg(Arg::with_name("rotation")
+                               .long("rotation")
+                               .help("Sets how the rotated files are named: content (default, \
+                                      by the first line), numeric, date, date-index or a regex \
+                                      with named captures date, index (higher is newer) and/or \
+                                      age (higher is older)")
+                               .takes_value(true))
+                      .arg(Arg::with_name("filter")
+                               .long("filter")
+                               .help("Only counts the lines selected by the filter, for example \
+                                      '\"committed\" AND NOT \"dry-run\"'. Supports \"text\", \
+                                      /regex/, level >= WARN, field == \"value\", AND, OR, NOT \
+                                      and parentheses. Applies on top of the filter of the \
+                                      monitor config, if any")
+                               .takes_value(true))
+                      .arg(Arg::with_name("remote")
+                               .long("remote")
+                               .help("Reads the log files from the host over ssh, the inputs \
+                                      are expanded on the host. Every file needed is fetched \
+                                      whole, from its start")
+                               .takes_value(true))
+                      .arg(Arg::with_name("remote-command")
+                               .long("remote-command")
+                               .help("Reads the log files with this command running the shell \
+                                      command lines instead of --remote, for example \
+                                      \"ssh -p 2222 HOST\", \"sh -c\" reads the local files \
+                                      the same way")
+                               .conflicts_with("remote")
+                               .takes_value(true))
+                      .arg(Arg::with_name("listen")
+                               .long("listen")
+                               .help("Listens for the log lines instead of reading files, plain \
+                                      lines or RFC 5424 syslog messages, for example \
+                                      \"udp://0.0.0.0:514\" or \"tcp://127.0.0.1:5140\"")
+                               .multiple(true)
+                               .number_of_values(1)
+                               .takes_value(true))
+                      .arg(Arg::with_name("route")
+                               .long("route")
+                               .help("Sends the syslog messages of the program only to the \
+                                      monitor, for example \"publisher=V1Publisher\", the \
+                                      other messages go to the monitors matching them")
+                               .requires("listen")
+                               .multiple(true)
+                               .number_of_values(1)
+                               .takes_value(true))
+                      .arg(Arg::with_name("interval")
+                               .long("interval")
+                               .help("Sets how often the result is shown while listening, \
+                                      default 1m. The monitors keep the hours/batches of their \
+                                      count")
+                               .requires("listen")
+                               .takes_value(true))
+                      .arg(Arg::with_name("snapshot")
+                               .long("snapshot")
+                               .help("Writes the result into the file instead of showing it \
+                                      while listening, for example for the textfile collector \
+                                      of the Prometheus node exporter")
+                               .requires("listen")
+                               .takes_value(true))
+                      .arg(Arg::with_name("stdin")
+                               .long("stdin")
+                               .help("Reads the logs from stdin, same as \"-\"")
+                               .conflicts_with("INPUT"))
                       .arg(Arg::with_name("debug")
                                .short("d")
                                .long("debug")
                                .help("Sets debug mode"))
+                      .arg(Arg::with_name("explain")
+                               .long("explain")
+                               .help("Dry run, shows the files selected and how a sample of \
+                                      lines is matched instead of the result"))
+                      .arg(Arg::with_name("samples")
+                               .long("samples")
+                               .help("Sets how many lines to show per stage for --explain, \
+                                      default 5")
+                               .takes_value(true))
+                      .arg(Arg::with_name("format")
+                               .short("f")
+                               .long("format")
+                               .help("Sets the output format")
+                               .possible_values(&["text", "json", "prometheus"])
+                               .takes_value(true))
+                      .arg(Arg::with_name("histogram")
+                               .long("histogram")
+                               .help("Shows the delay distribution columns and histogram for \
+                                      every hour/batch"))
+                      .arg(Arg::with_name("buckets")
+                               .long("buckets")
+                               .help("Sets the delay histogram buckets, for example \
+                                      \"1s,5s,30s,1m,5m\". Implies --histogram")
+                               .takes_value(true))
+                      .arg(Arg::with_name("stall")
+                               .long("stall")
+                               .help("Reports the periods without any matching line at least \
+                                      this long, for example \"10m\"")
+                               .takes_value(true))
+                      .arg(Arg::with_name("top")
+                               .long("top")
+                               .help("Shows the N most counted values of the dimension capture \
+                                      for every hour/batch, default 5")
+                               .takes_value(true))
+                      .arg(Arg::with_name("dedup")
+                               .long("dedup")
+                               .help("Drops the lines with an id capture already seen within \
+                                      this window, for example \"10m\", and reports them as \
+                                      duplicates. Windows over an hour are approximate: an id \
+                                      is kept for 1 to 2 windows, with a few false duplicates")
+                               .takes_value(true))
+                      .arg(Arg::with_name("pipeline")
+                               .long("pipeline")
+                               .help("Pairs the hours of a producer and a consumer monitor, \
+                                      shows the backlog and the watermark lag between them, \
+                                      for example \"NgPublisher:NgConsumer\" or \
+                                      \"NgPublisher:NgConsumer:10m\" to alert over 10 minutes \
+                                      of lag. Both monitors count by hour")
+                               .multiple(true)
+                               .number_of_values(1)
+                               .takes_value(true))
+                      .arg(Arg::with_name("history")
+                               .long("history")
+                               .help("Sets the history file, the hourly results are saved into it \
+                                      for the baseline of the next runs")
+                               .takes_value(true))
+                      .arg(Arg::with_name("anomaly")
+                               .long("anomaly")
+                               .help("Compares every hour with the same hour of the previous \
+                                      weeks, flags it if it is more than this many deviations \
+                                      away. Requires --history")
+                               .requires("history")
+                               .takes_value(true))
+                      .arg(Arg::with_name("weeks")
+                               .long("weeks")
+                               .help("Sets how many weeks the baseline looks back, default 4")
+                               .takes_value(true))
+                      .arg(Arg::with_name("mad")
+                               .long("mad")
+                               .help("Uses median absolute deviation instead of standard \
+                                      deviation for the baseline"))
+                      .subcommand(SubCommand::with_name("test-pattern")
+                                      .about("Shows how the sample lines are matched by a \
+                                              monitor or an ad-hoc regex, line by line")
+                                      .arg(Arg::with_name("monitor")
+                                               .short("m")
+                                               .long("monitor")
+                                               .help("Tests the matchers of the monitor, for \
+                                                      example Hour")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("regex")
+                                               .long("regex")
+                                               .help("Tests the regex of the lines, with the \
+                                                      named captures time, watermark and/or \
+                                                      count")
+                                               .conflicts_with("monitor")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("batch")
+                                               .long("batch")
+                                               .help("Tests the regex of the batch start lines, \
+                                                      with the named captures total and \
+                                                      optionally time and batch_id")
+                                               .requires("regex")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("start")
+                                               .long("start")
+                                               .help("Tests the regex of the start lines, paired \
+                                                      with the lines of --regex by the id \
+                                                      capture")
+                                               .requires("regex")
+                                               .conflicts_with("batch")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("fields")
+                                               .long("fields")
+                                               .help("Tests a structured line matcher instead \
+                                                      of a regex")
+                                               .possible_values(&["json", "logfmt"])
+                                               .conflicts_with_all(&["monitor", "regex"])
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("select")
+                                               .long("select")
+                                               .help("Only the lines where the field has the \
+                                                      value, for example event=committed, the \
+                                                      values are compared without unescaping")
+                                               .requires("fields")
+                                               .multiple(true)
+                                               .number_of_values(1)
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("capture")
+                                               .long("capture")
+                                               .help("Takes the time, watermark or count from \
+                                                      the field, for example \
+                                                      watermark=docWriteTime")
+                                               .requires("fields")
+                                               .multiple(true)
+                                               .number_of_values(1)
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("filter")
+                                               .long("filter")
+                                               .help("Only matches the lines selected by the \
+                                                      filter, or tests the filter alone")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("time")
+                                               .long("time")
+                                               .help("Sets the regex of the time stamp of the \
+                                                      lines, group 1 is the time stamp")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("line")
+                                               .short("l")
+                                               .long("line")
+                                               .help("A sample line")
+                                               .multiple(true)
+                                               .number_of_values(1)
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("expect")
+                                               .long("expect")
+                                               .help("Compares the report with the file, shows \
+                                                      the difference and fails if they differ")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("FILE")
+                                               .help("File of sample lines")
+                                               .index(1)))
                       .get_matches();
 
 
-    let prod = match matches.occurrences_of("debug") {
-        0 => true,
-        _ => false,
+    let prod = matches.occurrences_of("debug") == 0;
+
+    let format = matches.value_of("format")
+                        .unwrap_or("text")
+                        .parse::<Format>()
+                        .expect("Invalid output format");
+
+    let history = matches.value_of("history")
+                         .map(|h| History::load(h).expect("Failed to read history file."));
+
+    let mut options = ResultOptions {
+        buckets: match matches.value_of("buckets") {
+            Some(b) => parse_buckets(b).expect("Invalid histogram buckets"),
+            None if matches.is_present("histogram") => DEFAULT_BUCKETS.to_vec(),
+            None => Vec::new(),
+        },
+        stall: matches.value_of("stall").map(|s| parse_seconds(s).expect("Invalid stall duration")),
+        dedup: matches.value_of("dedup")
+                      .map(|d| parse_seconds(d).expect("Invalid dedup window")),
+        top: matches.value_of("top").unwrap_or("5").parse().expect("Invalid number of top values"),
+        anomaly: matches.value_of("anomaly").map(|a| {
+            AnomalyOptions {
+                history: Arc::new(history.clone().unwrap_or_default()),
+                weeks: matches.value_of("weeks")
+                              .unwrap_or("4")
+                              .parse()
+                              .expect("Invalid number of weeks"),
+                threshold: a.parse().expect("Invalid anomaly threshold"),
+                method: if matches.is_present("mad") {
+                    Method::Mad
+                } else {
+                    Method::Sigma
+                },
+            }
+        }),
+        columns: Columns::default(),
     };
 
-    println!("Name, lastSampleTime, Total(Batch size), Done, lastMsgTimeStamp, Efficiency(per \
-              min), Delay");
+    let config = matches.value_of("CONFIG").unwrap_or("hour");
+    if let Some(matches) = matches.subcommand_matches("test-pattern") {
+        let mut parser = match matches.value_of("monitor") {
+            Some(name) => {
+                monitors(config, prod).unwrap_or_else(|e| panic!("{}", e))
+                                      .into_iter()
+                                      .find(|tc| tc.name() == name)
+                                      .expect("Unknown monitor")
+                                      .into_parser()
+            }
+            None if matches.is_present("fields") => {
+                let format = matches.value_of("fields")
+                                    .unwrap()
+                                    .parse::<FieldFormat>()
+                                    .expect("Invalid field format");
+                let pairs = |name| -> Vec<(&str, &str)> {
+                    matches.values_of(name).map_or(Vec::new(), |v| {
+                        v.map(|p| {
+                             let mut pair = p.splitn(2, '=');
+                             (pair.next().unwrap(), pair.next().expect("Expects name=value"))
+                         })
+                         .collect()
+                    })
+                };
+                let mut matcher = FieldMatcher::new(format);
+                for (field, value) in pairs("select") {
+                    matcher = matcher.select(field, value);
+                }
+                for (name, field) in pairs("capture") {
+                    matcher = matcher.capture(name, field);
+                }
+                let parser = LogParser::new(matcher);
+                parser.validate().unwrap_or_else(|e| panic!("{}", e));
+                parser
+            }
+            None if !matches.is_present("regex") && matches.is_present("filter") => {
+                LogParser::new(filter(matches))
+            }
+            None => {
+                let regex = |name| {
+                    matches.value_of(name).map(|r| Regex::new(r).expect("Invalid regex"))
+                };
+                let pattern = regex("regex").expect("Requires --monitor, --regex or --fields");
+                let parser = match regex("start") {
+                    Some(start) => LogParser::new_pair(start, pattern),
+                    None => LogParser::new_batch(pattern, regex("batch")),
+                };
+                parser.validate().unwrap_or_else(|e| panic!("{}", e));
+                parser
+            }
+        };
+        if matches.is_present("filter") {
+            parser.set_filter(filter(matches));
+        }
+        if let Some(time) = matches.value_of("time") {
+            parser.set_time_regex(Regex::new(time).expect("Invalid time regex"));
+        }
+        let mut lines: Vec<String> = matches.values_of("line").map_or(Vec::new(), |v| {
+            v.map(|l| l.to_owned()).collect()
+        });
+        if let Some(file) = matches.value_of("FILE") {
+            let file = File::open(file).expect("Failed to open sample file.");
+            lines.extend(BufReader::new(file).lines().map_while(|l| l.ok()));
+        }
+        let report = test_pattern(&mut parser, &lines);
+        let expected = matches.value_of("expect").map(|e| {
+            read_to_string(e).expect("Failed to read expectations file.")
+        });
+        match expected {
+            Some(expected) => {
+                let diff = diff(&expected.lines().collect::<Vec<_>>(), &report);
+                if !diff.is_empty() {
+                    for line in diff {
+                        println!("{}", line);
+                    }
+                    process::exit(1);
+                }
+            }
+            None => {
+                for line in report {
+                    println!("{}", line);
+                }
+            }
+        }
+        return;
+    }
 
-    let monitors: Vec<_> = match matches.value_of("CONFIG").unwrap_or("hour") {
-        "hour" => vec![TcTool::new_hour(6, prod), TcTool::new_batch(6, prod)],
-        _ => panic!("Missing config"),
+    let mut monitors = monitors(config, prod).unwrap_or_else(|e| panic!("{}", e));
+    let inputs: Vec<String> = matches.values_of("INPUT")
+                                     .map_or(Vec::new(), |v| v.map(|i| i.to_owned()).collect());
+    let patterns = |name| -> Vec<Pattern> {
+        matches.values_of(name).map_or(Vec::new(), |v| {
+            v.map(|p| Pattern::new(p).expect("Invalid file name pattern")).collect()
+        })
+    };
+    let input = InputOptions {
+        recursive: matches.is_present("recursive"),
+        include: patterns("include"),
+        exclude: patterns("exclude"),
+        rotation: matches.value_of("rotation")
+                         .unwrap_or("content")
+                         .parse()
+                         .expect("Invalid rotation scheme"),
     };
 
-    let handlers: Vec<_> = monitors.into_iter()
-                                   .map(|mut tc| {
-                                       thread::spawn(move || {
-                                           tc.process_directory();
-                                           tc.print_result();
-                                       })
-                                   })
-                                   .collect();
-
-    for h in handlers {
-        h.join().unwrap();
+    let source = match (matches.value_of("remote-command"), matches.value_of("remote")) {
+        (Some(command), None) => {
+            let command: Vec<_> = command.split_whitespace().map(String::from).collect();
+            assert!(!command.is_empty(), "Invalid remote command");
+            Source::Remote(Remote::new(command))
+        }
+        (None, Some(host)) => Source::Remote(Remote::ssh(host)),
+        (None, None) => Source::Local,
+        (Some(_), Some(_)) => unreachable!("--remote-command conflicts with --remote"),
+    };
+
+    // the same columns for every monitor, under a single header
+    let columns = Columns {
+        delays: !options.buckets.is_empty(),
+        duplicates: options.dedup.is_some(),
+        ..Columns::default()
+    };
+    options.columns = monitors.iter().fold(columns, |columns, tc| columns.union(tc.columns()));
+
+    for tc in &mut monitors {
+        tc.set_source(source.clone());
+        if matches.is_present("filter") {
+            tc.set_filter(filter(&matches));
+        }
+        tc.set_options(options.clone());
+        if !inputs.is_empty() {
+            tc.set_inputs(inputs.clone(), input.clone());
+        }
+    }
+
+    let stdin = matches.is_present("stdin") || inputs.iter().any(|i| i == "-");
+    if stdin && inputs.len() > 1 {
+        panic!("\"-\" reads stdin only, it can't be mixed with files");
+    }
+
+    let pipelines = pipelines(&monitors, &matches);
+
+    if matches.is_present("explain") {
+        let samples = matches.value_of("samples")
+                             .unwrap_or("5")
+                             .parse()
+                             .expect("Invalid number of samples");
+        let mut explains: Vec<_> = monitors.iter()
+                                           .map(|tc| Explain::new(tc.name(), samples))
+                                           .collect();
+        if stdin {
+            let stdin = io::stdin();
+            explain_shared_reader(&mut monitors, &mut explains, stdin.lock());
+        } else {
+            for (tc, explain) in monitors.iter_mut().zip(explains.iter_mut()) {
+                tc.explain_directory(explain);
+            }
+        }
+        for mut explain in explains {
+            explain.summary();
+            for line in explain.lines {
+                println!("{}", line);
+            }
+        }
+        return;
+    }
+
+    if matches.is_present("listen") {
+        listen(monitors, &pipelines, &options.columns, &matches, format);
+        return;
+    }
+
+    let monitors: Vec<_> = if stdin {
+        // stdin can only be read once, all the monitors share it in a single pass
+        let stdin = io::stdin();
+        process_shared_reader(&mut monitors, stdin.lock());
+        monitors
+    } else {
+        process_all(monitors)
+    };
+
+    report(&monitors, &pipelines, &options.columns, format).print();
+
+    if let (Some(path), Some(mut history)) = (matches.value_of("history"), history) {
+        for tc in &monitors {
+            tc.record_history(&mut history);
+        }
+        history.save(path).expect("Failed to save history file.");
     }
 
 }
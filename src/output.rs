@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+use error::*;
+
+/// The CSV header of the text output, without the optional columns, see `Columns`
+pub const TEXT_HEADER: &str = "Name, lastSampleTime, Total(Batch size), Done, lastMsgTimeStamp, \
+                               Efficiency(per min), Delay";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// comma separated rows, one per hour/batch
+    Text,
+    /// one JSON object per hour/batch and line
+    Json,
+    /// Prometheus text exposition format
+    Prometheus,
+}
+
+impl ::std::str::FromStr for Format {
+    type Err = LogError;
+
+    fn from_str(s: &str) -> Result<Format> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "prometheus" => Ok(Format::Prometheus),
+            _ => Err(LogError::InvalidFormat),
+        }
+    }
+}
+
+/// `Output` collects the report of every monitor, so the monitors don't interleave and Prometheus
+/// samples can be grouped by metric family.
+pub struct Output {
+    pub format: Format,
+    /// the CSV header of the text output
+    pub header: String,
+    pub lines: Vec<String>,
+    /// metric family -> (type, samples)
+    metrics: BTreeMap<&'static str, (&'static str, Vec<String>)>,
+}
+
+impl Output {
+    pub fn new(format: Format) -> Output {
+        Output {
+            format,
+            header: TEXT_HEADER.to_owned(),
+            lines: Vec::new(),
+            metrics: BTreeMap::new(),
+        }
+    }
+
+    /// adds a line of text or JSON output
+    pub fn line(&mut self, line: String) {
+        self.lines.push(line);
+    }
+
+    /// adds a Prometheus sample. `suffix` is appended to the family name, for example "_bucket".
+    pub fn metric(&mut self,
+                  family: &'static str,
+                  kind: &'static str,
+                  suffix: &str,
+                  labels: &[(&str, &str)],
+                  value: f64) {
+        let labels: Vec<_> = labels.iter()
+                                   .map(|&(k, v)| format!("{}={}", k, prom_label(v)))
+                                   .collect();
+        let sample = format!("{}{}{{{}}} {}", family, suffix, labels.join(","), value);
+        self.metrics.entry(family).or_insert((kind, Vec::new())).1.push(sample);
+    }
+
+    pub fn print(&self) {
+        print!("{}", self.render());
+    }
+
+    /// the whole output, one line per row or sample
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        if self.format == Format::Text {
+            text.push_str(&self.header);
+            text.push('\n');
+        }
+        for line in &self.lines {
+            text.push_str(line);
+            text.push('\n');
+        }
+        for (family, &(kind, ref samples)) in &self.metrics {
+            text.push_str(&format!("# TYPE {} {}\n", family, kind));
+            for sample in samples {
+                text.push_str(sample);
+                text.push('\n');
+            }
+        }
+        text
+    }
+}
+
+/// quotes and escapes a Prometheus label value, only backslash, double quote and line feed are
+/// escaped in the text exposition format
+fn prom_label(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// quotes and escapes a string for JSON
+pub fn json_str(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// formats an optional number as JSON, null if missing
+pub fn json_opt<T: ToString>(value: Option<T>) -> String {
+    value.map_or("null".to_owned(), |v| v.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_format() {
+        assert_eq!("json".parse::<Format>().unwrap(), Format::Json);
+        assert_eq!("prometheus".parse::<Format>().unwrap(), Format::Prometheus);
+        assert!("xml".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn can_escape_json() {
+        assert_eq!(json_str(r#"a "b" \c"#), r#""a \"b\" \\c""#);
+        assert_eq!(json_str("\n\t"), r#""\n\u0009""#);
+    }
+
+    #[test]
+    fn can_escape_label() {
+        assert_eq!(prom_label("a \"b\" \\c\n\t"), "\"a \\\"b\\\" \\\\c\\n\t\"");
+    }
+
+    #[test]
+    fn can_group_metrics() {
+        let mut out = Output::new(Format::Prometheus);
+        out.metric("tc_done", "gauge", "", &[("monitor", "Hour")], 3.0);
+        out.metric("tc_delay", "summary", "_sum", &[("monitor", "Hour")], 1.5);
+        out.metric("tc_done", "gauge", "", &[("monitor", "Batch")], 4.0);
+
+        let samples = &out.metrics["tc_done"].1;
+        assert_eq!(samples[0], r#"tc_done{monitor="Hour"} 3"#);
+        assert_eq!(samples[1], r#"tc_done{monitor="Batch"} 4"#);
+        assert_eq!(out.metrics["tc_delay"].1[0], r#"tc_delay_sum{monitor="Hour"} 1.5"#);
+    }
+}
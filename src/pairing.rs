@@ -0,0 +1,93 @@
+use std::collections::VecDeque;
+use fnv::FnvHashMap;
+
+/// Longest wait, in seconds, for the other line of a message. A line waiting longer is given up,
+/// it stays unmatched.
+const MAX_WAIT: i64 = 24 * 3600;
+
+/// `Pairing` pairs the start and the end lines of the messages by their id. The files are read
+/// newest first, so the end of a message can be read before its start: whichever comes first
+/// waits for the other, up to `MAX_WAIT` away from the lines being read.
+#[derive(Debug, Default)]
+pub struct Pairing {
+    /// id -> (seconds, time stamp) of the starts without end yet
+    starts: FnvHashMap<String, (i64, String)>,
+    /// id -> (seconds, time stamp) of the ends without start yet
+    ends: FnvHashMap<String, (i64, String)>,
+    /// (seconds, id, start) of the waiting lines, in the order they were read
+    order: VecDeque<(i64, String, bool)>,
+}
+
+impl Pairing {
+    /// forgets the lines waiting more than `MAX_WAIT` away from `seconds`
+    fn expire(&mut self, seconds: i64) {
+        while self.order.front().is_some_and(|f| (seconds - f.0).abs() > MAX_WAIT) {
+            let (time, id, start) = self.order.pop_front().unwrap();
+            let waiting = if start { &mut self.starts } else { &mut self.ends };
+            if waiting.get(&id).is_some_and(|w| w.0 == time) {
+                waiting.remove(&id);
+            }
+        }
+    }
+
+    /// start records the start of the message, returns the time stamp of its end and the latency
+    /// in seconds if the end was read before.
+    pub fn start(&mut self, id: &str, seconds: i64, time: &str) -> Option<(String, i64)> {
+        self.expire(seconds);
+        match self.ends.remove(id) {
+            Some((end, end_time)) => Some((end_time, end - seconds)),
+            None => {
+                self.starts.insert(id.to_owned(), (seconds, time.to_owned()));
+                self.order.push_back((seconds, id.to_owned(), true));
+                None
+            }
+        }
+    }
+
+    /// end records the end of the message, returns the time stamp of its start and the latency
+    /// in seconds if the start was read before.
+    pub fn end(&mut self, id: &str, seconds: i64, time: &str) -> Option<(String, i64)> {
+        self.expire(seconds);
+        match self.starts.remove(id) {
+            Some((start, start_time)) => Some((start_time, seconds - start)),
+            None => {
+                self.ends.insert(id.to_owned(), (seconds, time.to_owned()));
+                self.order.push_back((seconds, id.to_owned(), false));
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_pair_in_any_order() {
+        let mut pairing = Pairing::default();
+        assert_eq!(pairing.start("1", 100, "t100"), None);
+        assert_eq!(pairing.end("1", 130, "t130"), Some(("t100".to_owned(), 30)));
+        // the end is read first
+        assert_eq!(pairing.end("2", 200, "t200"), None);
+        assert_eq!(pairing.start("2", 150, "t150"), Some(("t200".to_owned(), 50)));
+        // paired only once
+        assert_eq!(pairing.end("1", 140, "t140"), None);
+        assert_eq!(pairing.starts.len(), 0);
+        assert_eq!(pairing.ends.len(), 1);
+    }
+
+    #[test]
+    fn can_give_up_waiting() {
+        let mut pairing = Pairing::default();
+        assert_eq!(pairing.start("1", 0, "t0"), None);
+        assert_eq!(pairing.start("2", 3600, "t3600"), None);
+        assert_eq!(pairing.end("1", MAX_WAIT + 1, "late"), None);
+        assert_eq!(pairing.end("2", MAX_WAIT + 1, "late"),
+                   Some(("t3600".to_owned(), MAX_WAIT - 3599)));
+        assert_eq!((pairing.starts.len(), pairing.ends.len(), pairing.order.len()), (0, 1, 2));
+        // an older file is read after a newer one
+        assert_eq!(pairing.start("3", -MAX_WAIT, "older"), None);
+        assert_eq!((pairing.starts.len(), pairing.ends.len()), (1, 0));
+    }
+}
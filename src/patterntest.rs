@@ -0,0 +1,129 @@
+use logparser::{LineInfo, LogParser};
+
+fn captures(info: &LineInfo) -> String {
+    let captures: Vec<_> = info.captures()
+                               .iter()
+                               .map(|&(name, value)| format!("{}={:?}", name, value))
+                               .collect();
+    captures.join(" ")
+}
+
+/// test_pattern feeds the lines to the parser one by one, and reports for every line what
+/// `extract_info` and `extract_batch` return, and how the hour/batch counts change.
+pub fn test_pattern(parser: &mut LogParser, lines: &[String]) -> Vec<String> {
+    let mut report = Vec::new();
+    for (number, line) in lines.iter().enumerate() {
+        report.push(format!("{}: {}", number + 1, line));
+        report.push(match parser.extract_info(line) {
+            Some(info) => format!("  extract_info: {}", captures(&info)),
+            None => "  extract_info: no match".to_owned(),
+        });
+        report.push(match parser.extract_batch(line) {
+            Some(Ok(info)) => format!("  match_batch: {}", captures(&info)),
+            Some(Err(_)) => "  match_batch: no match".to_owned(),
+            None => "  match_batch: -".to_owned(),
+        });
+
+        let before = parser.counts();
+        parser.process_line(line);
+        let changes: Vec<_> = parser.counts()
+                                    .into_iter()
+                                    .filter(|c| !before.contains(c))
+                                    .map(|(key, total, done, lines)| {
+                                        let old = before.iter()
+                                                        .find(|b| b.0 == key)
+                                                        .map_or((key, 0, 0, 0), |b| *b);
+                                        format!("{} total {}->{} done {}->{} lines {}->{}",
+                                                key,
+                                                old.1,
+                                                total,
+                                                old.2,
+                                                done,
+                                                old.3,
+                                                lines)
+                                    })
+                                    .collect();
+        report.push(if changes.is_empty() {
+            "  stat: unchanged".to_owned()
+        } else {
+            format!("  stat: {}", changes.join(", "))
+        });
+    }
+    report
+}
+
+/// diff compares the lines line by line, returns the differences in unified diff style, or
+/// nothing if they are the same.
+pub fn diff(expected: &[&str], actual: &[String]) -> Vec<String> {
+    let (n, m) = (expected.len(), actual.len());
+    // lcs[i][j] is the longest common subsequence of expected[i..] and actual[j..]
+    let mut lcs = vec![vec![0; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    if lcs[0][0] == n && n == m {
+        return Vec::new();
+    }
+
+    let mut diff = vec!["--- expected".to_owned(), "+++ actual".to_owned()];
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && expected[i] == actual[j] {
+            diff.push(format!("  {}", expected[i]));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diff.push(format!("- {}", expected[i]));
+            i += 1;
+        } else {
+            diff.push(format!("+ {}", actual[j]));
+            j += 1;
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn can_test_pattern() {
+        let mut parser =
+            LogParser::new_batch(Regex::new(r"committed (?P<count>\d+)").unwrap(),
+                                 Some(Regex::new(r"Context contains (?P<total>\d+)").unwrap()));
+        let lines = ["2015-11-09 02:00:00,000 INFO Context contains 3 items",
+                     "2015-11-09 02:02:00,000 INFO committed 2",
+                     "no time stamp"];
+        let lines: Vec<_> = lines.iter().map(|l| l.to_string()).collect();
+
+        assert_eq!(test_pattern(&mut parser, &lines),
+                   ["1: 2015-11-09 02:00:00,000 INFO Context contains 3 items",
+                    "  extract_info: no match",
+                    "  match_batch: time=\"2015-11-09 02:00:00\" total=\"3\"",
+                    "  stat: 20151109020000 total 0->3 done 0->0 lines 0->0",
+                    "2: 2015-11-09 02:02:00,000 INFO committed 2",
+                    "  extract_info: time=\"2015-11-09 02:02:00\" count=\"2\"",
+                    "  match_batch: no match",
+                    "  stat: 20151109020000 total 3->3 done 0->2 lines 0->1",
+                    "3: no time stamp",
+                    "  extract_info: no match",
+                    "  match_batch: no match",
+                    "  stat: unchanged"]);
+    }
+
+    #[test]
+    fn can_diff() {
+        let actual: Vec<_> = ["a", "x", "c"].iter().map(|l| l.to_string()).collect();
+        assert!(diff(&["a", "x", "c"], &actual).is_empty());
+        assert_eq!(diff(&["a", "b", "c", "d"], &actual),
+                   ["--- expected", "+++ actual", "  a", "- b", "+ x", "  c", "- d"]);
+    }
+}
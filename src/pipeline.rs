@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+
+use error::*;
+use logstat::{hms, parse_seconds, LogTime, Stat};
+use output::{Format, Output, json_opt, json_str};
+use tc::TcTool;
+
+/// Default watermark lag, in seconds, over which the consumer is behind.
+const DEFAULT_LAG: i64 = 600;
+
+/// `Pipeline` pairs two monitors by hour, the producer and the consumer of the same messages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    pub producer: String,
+    pub consumer: String,
+    /// the consumer is behind if its watermark is more than this many seconds behind
+    pub lag: i64,
+}
+
+impl ::std::str::FromStr for Pipeline {
+    type Err = LogError;
+
+    /// "PRODUCER:CONSUMER" or "PRODUCER:CONSUMER:LAG", for example "Publisher:Writer:10m"
+    fn from_str(s: &str) -> Result<Pipeline> {
+        let parts: Vec<_> = s.split(':').collect();
+        let (producer, consumer, lag) = match parts[..] {
+            [p, c] => (p, c, DEFAULT_LAG),
+            [p, c, lag] => (p, c, parse_seconds(lag).map_err(|_| LogError::InvalidPipeline)?),
+            _ => return Err(LogError::InvalidPipeline),
+        };
+        if producer.is_empty() || consumer.is_empty() {
+            return Err(LogError::InvalidPipeline);
+        }
+        Ok(Pipeline {
+            producer: producer.to_owned(),
+            consumer: consumer.to_owned(),
+            lag,
+        })
+    }
+}
+
+/// `PipelineHour` is the throughput and the lag of a pipeline for one hour.
+#[derive(Debug, PartialEq)]
+pub struct PipelineHour {
+    pub hour: usize,
+    pub produced: u32,
+    pub consumed: u32,
+    /// produced - consumed since the first hour reported
+    pub backlog: i64,
+    /// seconds between the last watermark of the producer and the consumer in the hour
+    pub lag: Option<i64>,
+    /// why the consumer is behind: "lag", and/or "backlog" if it grew two hours in a row
+    pub alerts: Vec<&'static str>,
+}
+
+impl Pipeline {
+    /// validate checks the producer and the consumer are monitors counting by hour, the batches
+    /// of a batch monitor can't be paired by hour.
+    pub fn validate(&self, monitors: &[TcTool]) -> Result<()> {
+        for name in &[&self.producer, &self.consumer] {
+            match monitors.iter().find(|tc| tc.name() == name.as_str()) {
+                Some(tc) if !tc.is_batch() => {}
+                _ => return Err(LogError::InvalidPipeline),
+            }
+        }
+        Ok(())
+    }
+
+    /// hours pairs the hours of the producer and the consumer, both oldest first. The hours up to
+    /// the oldest hour of both monitors are skipped, they are likely to be incomplete. The newest
+    /// hour is still in progress, it never alerts.
+    pub fn hours(&self,
+                 producer: &[(usize, &Stat)],
+                 consumer: &[(usize, &Stat)])
+                 -> Vec<PipelineHour> {
+        let first = producer.first().into_iter().chain(consumer.first()).map(|s| s.0).max();
+        let mut paired: BTreeMap<usize, (Option<&Stat>, Option<&Stat>)> = BTreeMap::new();
+        for &(hour, stat) in producer {
+            paired.entry(hour).or_insert((None, None)).0 = Some(stat);
+        }
+        for &(hour, stat) in consumer {
+            paired.entry(hour).or_insert((None, None)).1 = Some(stat);
+        }
+
+        let newest = paired.keys().next_back().cloned();
+        let mut hours = Vec::new();
+        let mut backlog = 0;
+        let mut grew = false;
+        for (hour, (p, c)) in paired.into_iter().filter(|h| Some(h.0) > first) {
+            let produced = p.map_or(0, |s| s.done);
+            let consumed = c.map_or(0, |s| s.done);
+            backlog += i64::from(produced) - i64::from(consumed);
+            let watermark = |s: Option<&Stat>| {
+                s.and_then(|s| s.last_time_stamp.parse::<LogTime>().ok()).map(|t| t.seconds())
+            };
+            let lag = match (watermark(p), watermark(c)) {
+                (Some(p), Some(c)) => Some((p - c).max(0)),
+                _ => None,
+            };
+            let mut alerts = Vec::new();
+            // the newest hour is partial, it's neither behind nor caught up yet
+            if Some(hour) != newest {
+                if lag.is_some_and(|l| l > self.lag) {
+                    alerts.push("lag");
+                }
+                if produced > consumed && grew {
+                    alerts.push("backlog");
+                }
+            }
+            grew = produced > consumed;
+            hours.push(PipelineHour {
+                hour,
+                produced,
+                consumed,
+                backlog,
+                lag,
+                alerts,
+            });
+        }
+        hours
+    }
+
+    /// report adds the hours into the output, newest first.
+    pub fn report(&self, hours: &[PipelineHour], out: &mut Output) {
+        let name = format!("{}->{}", self.producer, self.consumer);
+        for (count, h) in hours.iter().rev().enumerate() {
+            match out.format {
+                Format::Text => {
+                    out.line(format!("{}-{},pipeline, {}, {}, {}, {}, {}{}",
+                                     name,
+                                     count,
+                                     h.hour,
+                                     h.produced,
+                                     h.consumed,
+                                     h.backlog,
+                                     h.lag.map_or(String::new(), hms),
+                                     if h.alerts.is_empty() {
+                                         String::new()
+                                     } else {
+                                         format!(", BEHIND {}", h.alerts.join(" "))
+                                     }))
+                }
+                Format::Json => {
+                    let alerts: Vec<_> = h.alerts.iter().map(|a| json_str(a)).collect();
+                    out.line(format!(concat!(r#"{{"name":{},"index":{},"pipeline":{{"#,
+                                             r#""producer":{},"consumer":{},"hour":{},"#,
+                                             r#""produced":{},"consumed":{},"backlog":{},"#,
+                                             r#""lag":{},"alerts":[{}]}}}}"#),
+                                     json_str(&name),
+                                     count,
+                                     json_str(&self.producer),
+                                     json_str(&self.consumer),
+                                     h.hour,
+                                     h.produced,
+                                     h.consumed,
+                                     h.backlog,
+                                     json_opt(h.lag),
+                                     alerts.join(",")))
+                }
+                Format::Prometheus => {
+                    let hour = h.hour.to_string();
+                    let labels = [("producer", self.producer.as_str()),
+                                  ("consumer", self.consumer.as_str()),
+                                  ("hour", hour.as_str())];
+                    out.metric("tc_pipeline_produced", "gauge", "", &labels, f64::from(h.produced));
+                    out.metric("tc_pipeline_consumed", "gauge", "", &labels, f64::from(h.consumed));
+                    out.metric("tc_pipeline_backlog", "gauge", "", &labels, h.backlog as f64);
+                    if let Some(lag) = h.lag {
+                        out.metric("tc_pipeline_lag_seconds", "gauge", "", &labels, lag as f64);
+                    }
+                    let behind = if h.alerts.is_empty() { 0.0 } else { 1.0 };
+                    out.metric("tc_pipeline_behind", "gauge", "", &labels, behind);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use template;
+
+    fn stat(done: u32, watermark: &str) -> Stat {
+        Stat {
+            done,
+            last_time_stamp: watermark.to_owned(),
+            ..Stat::default()
+        }
+    }
+
+    #[test]
+    fn can_parse_pipeline() {
+        let pipeline = "Pub:Con:5m".parse::<Pipeline>().unwrap();
+        assert_eq!((pipeline.producer.as_str(), pipeline.consumer.as_str(), pipeline.lag),
+                   ("Pub", "Con", 300));
+        assert_eq!("Pub:Con".parse::<Pipeline>().unwrap().lag, DEFAULT_LAG);
+        assert!("Pub".parse::<Pipeline>().is_err());
+        assert!("Pub::5m".parse::<Pipeline>().is_err());
+        assert!("Pub:Con:5d".parse::<Pipeline>().is_err());
+    }
+
+    #[test]
+    fn can_pair_hours() {
+        let producer = [stat(10, "2015-11-09 00:59:00"),
+                        stat(100, "2015-11-09 01:59:00"),
+                        stat(100, "2015-11-09 02:59:00"),
+                        stat(100, "2015-11-09 03:59:00"),
+                        stat(100, "2015-11-09 04:10:00")];
+        let consumer = [stat(100, "2015-11-09 01:58:00"),
+                        stat(80, "2015-11-09 02:40:00"),
+                        stat(90, "2015-11-09 03:58:00"),
+                        stat(10, "2015-11-09 03:59:00")];
+        let pipeline = "Pub:Con".parse::<Pipeline>().unwrap();
+        let hours = pipeline.hours(&[(2015110900, &producer[0]),
+                                     (2015110901, &producer[1]),
+                                     (2015110902, &producer[2]),
+                                     (2015110903, &producer[3]),
+                                     (2015110904, &producer[4])],
+                                   &[(2015110901, &consumer[0]),
+                                     (2015110902, &consumer[1]),
+                                     (2015110903, &consumer[2]),
+                                     (2015110904, &consumer[3])]);
+        // 00 and 01 are the first hours of the monitors
+        assert_eq!(hours.len(), 3);
+        assert_eq!(hours[0],
+                   PipelineHour {
+                       hour: 2015110902,
+                       produced: 100,
+                       consumed: 80,
+                       backlog: 20,
+                       lag: Some(19 * 60),
+                       alerts: vec!["lag"],
+                   });
+        assert_eq!((hours[1].backlog, hours[1].lag), (30, Some(60)));
+        assert_eq!(hours[1].alerts, ["backlog"]);
+        // 04 is still in progress
+        assert_eq!((hours[2].backlog, hours[2].lag), (120, Some(11 * 60)));
+        assert!(hours[2].alerts.is_empty());
+
+        let mut out = Output::new(Format::Text);
+        pipeline.report(&hours, &mut out);
+        assert_eq!(out.lines,
+                   ["Pub->Con-0,pipeline, 2015110904, 100, 10, 120, 00:11:00",
+                    "Pub->Con-1,pipeline, 2015110903, 100, 90, 30, 00:01:00, BEHIND backlog",
+                    "Pub->Con-2,pipeline, 2015110902, 100, 80, 20, 00:19:00, BEHIND lag"]);
+    }
+
+    #[test]
+    fn can_validate_monitors() {
+        let monitor = |name| TcTool::from_template(template::find(name).unwrap(), 6, false);
+        let monitors = [monitor("Hour"), monitor("Batch"), monitor("NgPublisher")];
+        assert!("NgPublisher:Hour".parse::<Pipeline>().unwrap().validate(&monitors).is_ok());
+        // the batches aren't hours
+        assert!("Hour:Batch".parse::<Pipeline>().unwrap().validate(&monitors).is_err());
+        assert!("Batch:Hour".parse::<Pipeline>().unwrap().validate(&monitors).is_err());
+        assert!("Hour:Nothing".parse::<Pipeline>().unwrap().validate(&monitors).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use regex::Regex;
+use std::cmp::Reverse;
+use std::path::Path;
+
+use error::*;
+use logresult::trim_index;
+
+/// `Rotation` is the naming scheme of the rotated log files, it decides the order the files are
+/// read: newest first.
+#[derive(Debug, Clone, Default)]
+pub enum Rotation {
+    /// by the time stamp of the first line in the files, see `input::sorted_path`
+    #[default]
+    Content,
+    /// log4j style, higher suffix is older: name.log, name.log.1, ... name.log.10
+    Numeric,
+    /// regex on the file name with the named captures `date`, `index` (higher is newer) and/or
+    /// `age` (higher is older), see `DATE` and `DATE_INDEX` for the common ones
+    Name(Regex),
+}
+
+/// `RotationKey` sorts the files newest first. The current file, which has no rotation suffix,
+/// is always the newest.
+pub type RotationKey = (bool, Reverse<u64>, i64);
+
+/// a date in the name: batch.log, batch.log.2026-10-16, batch-20261015.log
+const DATE: &str = r"(?P<date>\d{4}-?\d{2}-?\d{2})";
+/// a date and an index in the name, higher index is newer within a day (logback
+/// `%d{yyyy-MM-dd}.%i`): batch.log, batch-2026-10-16-1.log, batch.2026-10-16.0.log
+const DATE_INDEX: &str = r"(?P<date>\d{4}-?\d{2}-?\d{2})(?:[._-](?P<index>\d+))?";
+
+impl ::std::str::FromStr for Rotation {
+    type Err = LogError;
+
+    /// one of "content", "numeric", "date", "date-index", or a regex with named captures.
+    fn from_str(s: &str) -> Result<Rotation> {
+        match s {
+            "content" => Ok(Rotation::Content),
+            "numeric" => Ok(Rotation::Numeric),
+            "date" => Ok(Rotation::Name(Regex::new(DATE).unwrap())),
+            "date-index" => Ok(Rotation::Name(Regex::new(DATE_INDEX).unwrap())),
+            _ => {
+                let regex = Regex::new(s).map_err(|_| LogError::InvalidRotation)?;
+                if regex.capture_names().flatten().any(|n| n == "date" || n == "index" || n == "age") {
+                    Ok(Rotation::Name(regex))
+                } else {
+                    Err(LogError::InvalidRotation)
+                }
+            }
+        }
+    }
+}
+
+impl Rotation {
+    /// Returns the sort key of the file, None if the order comes from the content.
+    pub fn key(&self, path: &Path) -> Option<RotationKey> {
+        match *self {
+            Rotation::Content => None,
+            Rotation::Numeric => {
+                Some(match path.extension().and_then(|e| e.to_str()).and_then(|e| e.parse().ok()) {
+                    Some(age) => (true, Reverse(0), age),
+                    None => (false, Reverse(0), 0),
+                })
+            }
+            Rotation::Name(ref regex) => {
+                let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+                Some(match regex.captures(name) {
+                    Some(c) => {
+                        let number = |n| c.name(n).map_or(0, |m| trim_index(m.as_str()) as i64);
+                        (true, Reverse(number("date") as u64), number("age") - number("index"))
+                    }
+                    None => (false, Reverse(0), 0),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(rotation: &str, names: &[&str]) -> Vec<String> {
+        let rotation = rotation.parse::<Rotation>().unwrap();
+        let mut names = names.to_vec();
+        names.sort_by_key(|n| rotation.key(Path::new(n)));
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn can_sort_numeric() {
+        assert_eq!(sorted("numeric", &["tc.log.10", "tc.log.2", "tc.log", "tc.log.1"]),
+                   ["tc.log", "tc.log.1", "tc.log.2", "tc.log.10"]);
+    }
+
+    #[test]
+    fn can_sort_date() {
+        assert_eq!(sorted("date",
+                          &["batch.log.2026-09-30", "batch.log.2026-10-16", "batch.log",
+                            "batch.log.2026-10-15"]),
+                   ["batch.log", "batch.log.2026-10-16", "batch.log.2026-10-15",
+                    "batch.log.2026-09-30"]);
+        assert_eq!(sorted("date", &["batch-20261015.log", "batch-20261016.log"]),
+                   ["batch-20261016.log", "batch-20261015.log"]);
+    }
+
+    #[test]
+    fn can_sort_date_index() {
+        assert_eq!(sorted("date-index",
+                          &["batch-2026-10-15-2.log", "batch-2026-10-16-1.log", "batch.log",
+                            "batch-2026-10-16-2.log", "batch-2026-10-15-10.log"]),
+                   ["batch.log", "batch-2026-10-16-2.log", "batch-2026-10-16-1.log",
+                    "batch-2026-10-15-10.log", "batch-2026-10-15-2.log"]);
+        // logback %d{yyyy-MM-dd}.%i
+        assert_eq!(sorted("date-index",
+                          &["app.2026-10-16.0.log", "app.2026-10-16.1.log", "app.2026-10-17.0.log"]),
+                   ["app.2026-10-17.0.log", "app.2026-10-16.1.log", "app.2026-10-16.0.log"]);
+    }
+
+    #[test]
+    fn can_sort_custom() {
+        assert_eq!(sorted(r"_(?P<date>\d{8})_(?P<age>\d+)$",
+                          &["app_20261016_1", "app_20261016_0", "app", "app_20261015_0"]),
+                   ["app", "app_20261016_0", "app_20261016_1", "app_20261015_0"]);
+        assert!("no captures (\\d+)".parse::<Rotation>().is_err());
+        assert!("(?P<date>".parse::<Rotation>().is_err());
+    }
+
+    #[test]
+    fn content_keeps_order() {
+        assert_eq!(sorted("content", &["b.log.1", "a.log", "b.log"]), ["b.log.1", "a.log", "b.log"]);
+    }
+}
@@ -0,0 +1,247 @@
+use fnv::FnvHashMap;
+
+use output::json_str;
+
+/// Upper limit of the missing ranges kept per hour/batch, the others are only counted.
+const MAX_RANGES: usize = 10;
+
+/// Longest backwards jump of a late message, a number further below the previous one is a
+/// restart of the sequence.
+const MAX_REORDER: u64 = 1000;
+
+/// `SequenceEvent` is how a sequence number follows the previous one of its partition.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SequenceEvent {
+    /// the next number, or the first one seen
+    InOrder,
+    /// the numbers first ... last are missing
+    Gap(u64, u64),
+    /// not above the previous number, a late or repeated message
+    OutOfOrder(u64),
+    /// the sequence starts again from 0 or 1, or from far below the previous number
+    Reset,
+}
+
+/// `Sequences` tracks the last sequence number of every partition. The numbers only follow each
+/// other within a file, the files are read newest first.
+#[derive(Debug, Default)]
+pub struct Sequences {
+    last: FnvHashMap<String, u64>,
+}
+
+impl Sequences {
+    pub fn next(&mut self, partition: &str, seq: u64) -> SequenceEvent {
+        let last = match self.last.get_mut(partition) {
+            Some(last) => last,
+            None => {
+                self.last.insert(partition.to_owned(), seq);
+                return SequenceEvent::InOrder;
+            }
+        };
+        let event = match seq {
+            s if Some(s) == last.checked_add(1) => SequenceEvent::InOrder,
+            s if s > *last => SequenceEvent::Gap(*last + 1, s - 1),
+            s if (s <= 1 && *last > 1) || *last - s > MAX_REORDER => SequenceEvent::Reset,
+            s => return SequenceEvent::OutOfOrder(s),
+        };
+        *last = seq;
+        event
+    }
+
+    /// forgets the numbers of the previous file
+    pub fn clear(&mut self) {
+        self.last.clear();
+    }
+}
+
+/// `SequenceStat` sums up the sequence events of a hour/batch.
+#[derive(Debug, Clone, Default)]
+pub struct SequenceStat {
+    /// how many ranges of numbers are missing
+    pub gaps: u32,
+    /// how many numbers are missing
+    pub missing: u64,
+    pub out_of_order: u32,
+    pub resets: u32,
+    /// the first missing ranges: (partition, first, last)
+    pub ranges: Vec<(String, u64, u64)>,
+}
+
+impl SequenceStat {
+    pub fn record(&mut self, partition: &str, event: SequenceEvent) {
+        match event {
+            SequenceEvent::InOrder => {}
+            SequenceEvent::Gap(first, last) => {
+                self.gaps += 1;
+                self.missing += last - first + 1;
+                if self.ranges.len() < MAX_RANGES {
+                    self.ranges.push((partition.to_owned(), first, last));
+                }
+            }
+            SequenceEvent::OutOfOrder(seq) => {
+                self.out_of_order += 1;
+                self.fill(partition, seq);
+            }
+            SequenceEvent::Reset => self.resets += 1,
+        }
+    }
+
+    /// fill takes a late number out of the missing range it falls in, if the range is tracked by
+    /// this hour/batch
+    fn fill(&mut self, partition: &str, seq: u64) {
+        let index = match self.ranges
+                              .iter()
+                              .position(|r| r.0 == partition && r.1 <= seq && seq <= r.2) {
+            Some(index) => index,
+            None => return,
+        };
+        self.missing -= 1;
+        let (first, last) = (self.ranges[index].1, self.ranges[index].2);
+        if first == last {
+            self.gaps -= 1;
+            self.ranges.remove(index);
+        } else if seq == first {
+            self.ranges[index].1 += 1;
+        } else if seq == last {
+            self.ranges[index].2 -= 1;
+        } else {
+            // the range splits in two, the second one is only counted if there is no room
+            self.gaps += 1;
+            self.ranges[index].2 = seq - 1;
+            if self.ranges.len() < MAX_RANGES {
+                self.ranges.insert(index + 1, (partition.to_owned(), seq + 1, last));
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: &SequenceStat) {
+        self.gaps += other.gaps;
+        self.missing += other.missing;
+        self.out_of_order += other.out_of_order;
+        self.resets += other.resets;
+        let room = MAX_RANGES.saturating_sub(self.ranges.len());
+        self.ranges.extend(other.ranges.iter().take(room).cloned());
+    }
+
+    /// follow the format "gaps, missing, out of order, resets"
+    pub fn to_str(&self) -> String {
+        format!("{}, {}, {}, {}", self.gaps, self.missing, self.out_of_order, self.resets)
+    }
+
+    /// the missing ranges as "first-last", prefixed by the partition if any
+    pub fn ranges_str(&self) -> String {
+        let ranges: Vec<_> = self.ranges
+                                 .iter()
+                                 .map(|&(ref partition, first, last)| {
+                                     let range = if first == last {
+                                         first.to_string()
+                                     } else {
+                                         format!("{}-{}", first, last)
+                                     };
+                                     if partition.is_empty() {
+                                         range
+                                     } else {
+                                         format!("{}:{}", partition, range)
+                                     }
+                                 })
+                                 .collect();
+        ranges.join(", ")
+    }
+
+    pub fn to_json(&self) -> String {
+        let ranges: Vec<_> = self.ranges
+                                 .iter()
+                                 .map(|&(ref partition, first, last)| {
+                                     format!(r#"{{"partition":{},"first":{},"last":{}}}"#,
+                                             json_str(partition),
+                                             first,
+                                             last)
+                                 })
+                                 .collect();
+        format!(r#"{{"gaps":{},"missing":{},"out_of_order":{},"resets":{},"ranges":[{}]}}"#,
+                self.gaps,
+                self.missing,
+                self.out_of_order,
+                self.resets,
+                ranges.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_track_sequences() {
+        let mut sequences = Sequences::default();
+        let events: Vec<_> = [("", 5), ("", 6), ("", 9), ("", 7), ("", 9), ("", 1), ("", 2)]
+                                 .iter()
+                                 .map(|&(p, s)| sequences.next(p, s))
+                                 .collect();
+        assert_eq!(events,
+                   [SequenceEvent::InOrder,
+                    SequenceEvent::InOrder,
+                    SequenceEvent::Gap(7, 8),
+                    SequenceEvent::OutOfOrder(7),
+                    SequenceEvent::OutOfOrder(9),
+                    SequenceEvent::Reset,
+                    SequenceEvent::InOrder]);
+
+        // every partition has its own sequence
+        assert_eq!(sequences.next("p1", 10), SequenceEvent::InOrder);
+        assert_eq!(sequences.next("p1", 11), SequenceEvent::InOrder);
+        assert_eq!(sequences.next("", 3), SequenceEvent::InOrder);
+        sequences.clear();
+        assert_eq!(sequences.next("p1", 20), SequenceEvent::InOrder);
+
+        // a restart above 1 is a reset, after which the sequence goes on from it
+        assert_eq!(sequences.next("p1", 5000), SequenceEvent::Gap(21, 4999));
+        assert_eq!(sequences.next("p1", 100), SequenceEvent::Reset);
+        assert_eq!(sequences.next("p1", 101), SequenceEvent::InOrder);
+        assert_eq!(sequences.next("p2", u64::MAX), SequenceEvent::InOrder);
+        assert_eq!(sequences.next("p2", u64::MAX), SequenceEvent::OutOfOrder(u64::MAX));
+    }
+
+    #[test]
+    fn can_sum_up_events() {
+        let mut stat = SequenceStat::default();
+        stat.record("", SequenceEvent::Gap(7, 8));
+        stat.record("p1", SequenceEvent::Gap(3, 3));
+        stat.record("", SequenceEvent::OutOfOrder(9));
+        stat.record("", SequenceEvent::InOrder);
+        assert_eq!(stat.to_str(), "2, 3, 1, 0");
+        assert_eq!(stat.ranges_str(), "7-8, p1:3");
+
+        let mut merged = SequenceStat::default();
+        merged.record("", SequenceEvent::Reset);
+        merged.merge(&stat);
+        assert_eq!(merged.to_str(), "2, 3, 1, 1");
+        assert_eq!(merged.to_json(),
+                   concat!(r#"{"gaps":2,"missing":3,"out_of_order":1,"resets":1,"ranges":["#,
+                           r#"{"partition":"","first":7,"last":8},"#,
+                           r#"{"partition":"p1","first":3,"last":3}]}"#));
+    }
+
+    #[test]
+    fn can_fill_gaps_with_late_numbers() {
+        let mut stat = SequenceStat::default();
+        stat.record("", SequenceEvent::Gap(10, 20));
+        stat.record("p1", SequenceEvent::Gap(3, 3));
+        // from the other partition, and out of the ranges
+        stat.record("p1", SequenceEvent::OutOfOrder(10));
+        stat.record("", SequenceEvent::OutOfOrder(21));
+        assert_eq!(stat.to_str(), "2, 12, 2, 0");
+
+        stat.record("", SequenceEvent::OutOfOrder(10));
+        stat.record("", SequenceEvent::OutOfOrder(20));
+        stat.record("", SequenceEvent::OutOfOrder(15));
+        assert_eq!(stat.to_str(), "3, 9, 5, 0");
+        assert_eq!(stat.ranges_str(), "11-14, 16-19, p1:3");
+
+        // repeated, already filled
+        stat.record("", SequenceEvent::OutOfOrder(15));
+        stat.record("p1", SequenceEvent::OutOfOrder(3));
+        assert_eq!(stat.to_str(), "2, 8, 7, 0");
+        assert_eq!(stat.ranges_str(), "11-14, 16-19");
+    }
+}
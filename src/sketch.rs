@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+
+/// Relative accuracy of the quantiles returned by `QuantileSketch`.
+const ACCURACY: f64 = 0.01;
+
+/// Upper limit of the buckets kept by a sketch. 1% accuracy needs about 670 buckets to cover one
+/// second up to a week, about 1010 in milliseconds, past this limit the lowest buckets are
+/// collapsed together.
+const MAX_BUCKETS: usize = 1024;
+
+/// `QuantileSketch` is a streaming quantile estimator for non-negative values (DDSketch).
+/// Values are counted in logarithmic buckets, so the memory depends on the range of the values
+/// rather than the number of samples, and every quantile is within 1% of the real value.
+#[derive(Debug, Clone, Default)]
+pub struct QuantileSketch {
+    /// values too small for the log buckets, include zero
+    zero: u32,
+    buckets: BTreeMap<i32, u32>,
+    count: u32,
+}
+
+fn gamma() -> f64 {
+    (1.0 + ACCURACY) / (1.0 - ACCURACY)
+}
+
+impl QuantileSketch {
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn insert(&mut self, value: f64) {
+        self.count += 1;
+        if value < 1e-3 {
+            self.zero += 1;
+            return;
+        }
+        let index = (value.ln() / gamma().ln()).ceil() as i32;
+        *self.buckets.entry(index).or_insert(0) += 1;
+        self.collapse();
+    }
+
+    pub fn merge(&mut self, other: &QuantileSketch) {
+        self.count += other.count;
+        self.zero += other.zero;
+        for (index, count) in &other.buckets {
+            *self.buckets.entry(*index).or_insert(0) += *count;
+        }
+        self.collapse();
+    }
+
+    /// Returns the estimated value at quantile `q` (0.0 ... 1.0), None if the sketch is empty.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        // nearest rank, the smallest value with at least q of the samples at or below it
+        let rank = ((q.clamp(0.0, 1.0) * f64::from(self.count)).ceil() as u32).max(1) - 1;
+        if rank < self.zero {
+            return Some(0.0);
+        }
+        let mut seen = self.zero;
+        for (index, count) in &self.buckets {
+            seen += *count;
+            if seen > rank {
+                // the middle of the bucket (gamma^(i-1), gamma^i]
+                return Some(2.0 * gamma().powi(*index) / (gamma() + 1.0));
+            }
+        }
+        None
+    }
+
+    /// merges the lowest buckets when the sketch grows past `MAX_BUCKETS`, only the accuracy of
+    /// the lowest quantiles is lost.
+    fn collapse(&mut self) {
+        while self.buckets.len() > MAX_BUCKETS {
+            let lowest = *self.buckets.keys().next().unwrap();
+            let count = self.buckets.remove(&lowest).unwrap();
+            *self.buckets.values_mut().next().unwrap() += count;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.unwrap();
+        assert!((actual - expected).abs() <= expected * ACCURACY + 1e-9,
+                "{} is not close to {}",
+                actual,
+                expected);
+    }
+
+    #[test]
+    fn can_estimate_quantiles() {
+        let mut sketch = QuantileSketch::default();
+        assert_eq!(sketch.quantile(0.5), None);
+
+        for v in 1..1001 {
+            sketch.insert(v as f64);
+        }
+        assert_eq!(sketch.count(), 1000);
+        assert_close(sketch.quantile(0.0), 1.0);
+        assert_close(sketch.quantile(0.5), 500.0);
+        assert_close(sketch.quantile(0.9), 900.0);
+        assert_close(sketch.quantile(0.99), 990.0);
+        assert_close(sketch.quantile(1.0), 1000.0);
+    }
+
+    #[test]
+    fn can_merge_and_count_zero() {
+        let mut a = QuantileSketch::default();
+        let mut b = QuantileSketch::default();
+        for _ in 0..90 {
+            a.insert(0.0);
+        }
+        for _ in 0..10 {
+            b.insert(3600.0);
+        }
+        a.merge(&b);
+        assert_eq!(a.count(), 100);
+        assert_eq!(a.quantile(0.5), Some(0.0));
+        assert_close(a.quantile(0.95), 3600.0);
+    }
+
+    #[test]
+    fn memory_is_bounded() {
+        let mut sketch = QuantileSketch::default();
+        let mut v = 0.001;
+        while v < 1e12 {
+            sketch.insert(v);
+            v *= 1.01;
+        }
+        assert!(sketch.buckets.len() <= MAX_BUCKETS);
+        assert_close(sketch.quantile(1.0), v / 1.01);
+    }
+}
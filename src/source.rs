@@ -0,0 +1,262 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use input::{head_timestamp, list_files, sorted_by, InputOptions, HEAD_LINES};
+
+/// `Source` is where the log files of a monitor are listed and read.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Source {
+    /// the local file system
+    #[default]
+    Local,
+    /// a host reached by a command running shell command lines, see `Remote`
+    Remote(Remote),
+}
+
+/// `Remote` runs the shell command lines on a host, for example `ssh -o BatchMode=yes host`.
+/// The inputs are expanded by the remote shell and the files are streamed with `cat`, so only
+/// the files needed for the count are fetched, newest first. A file is fetched from its start,
+/// the hours before the ones counted are transferred as well. `sh -c` runs them locally instead,
+/// a stand-in for testing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Remote {
+    command: Vec<String>,
+}
+
+/// lists the inputs: "=path" for a file, "+path" for a file of a directory or a glob pattern.
+/// IFS is empty so the patterns are expanded without being split.
+const LIST: &str = r#"IFS=
+for p in "$@"; do
+  if [ -f "$p" ]; then printf '=%s\n' "$p"
+  elif [ -d "$p" ]; then find "$p" DEPTH -type f | sed 's/^/+/'
+  else for f in $p; do if [ -f "$f" ]; then printf '+%s\n' "$f"; fi; done
+  fi
+done"#;
+
+/// prints the head of every file, each after a line with the record separator and its path
+const HEADS: &str = r#"for f in "$@"; do printf '\036%s\n' "$f"; head -n LINES -- "$f"; done"#;
+
+/// quotes the text as a single shell word
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+impl Remote {
+    /// `command` is the program and its arguments, the command line is added as last argument.
+    pub fn new(command: Vec<String>) -> Remote {
+        Remote { command }
+    }
+
+    pub fn ssh(host: &str) -> Remote {
+        Remote::new(vec!["ssh".to_owned(), "-o".to_owned(), "BatchMode=yes".to_owned(),
+                         host.to_owned()])
+    }
+
+    /// starts the script with the arguments, its output is piped
+    fn spawn<S: AsRef<str>>(&self, script: &str, args: &[S]) -> io::Result<Child> {
+        let mut line = format!("sh -c {} sh", quote(script));
+        for arg in args {
+            line.push(' ');
+            line.push_str(&quote(arg.as_ref()));
+        }
+        Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .arg(line)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+    }
+
+    /// runs the script with the arguments, returns the lines of its output. A command which
+    /// fails, for example ssh not reaching the host, is an error.
+    fn run<S: AsRef<str>>(&self, script: &str, args: &[S]) -> io::Result<Vec<String>> {
+        let output = self.spawn(script, args)?.wait_with_output()?;
+        if !output.status.success() {
+            let command = self.command.join(" ");
+            return Err(io::Error::other(format!("{} failed: {}", command, output.status)));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).lines().map(String::from).collect())
+    }
+
+    /// Same as `list_files`, the inputs are expanded on the host.
+    pub fn list(&self, inputs: &[String], options: &InputOptions) -> io::Result<Vec<PathBuf>> {
+        let depth = if options.recursive { "" } else { "-maxdepth 1" };
+        let mut files: Vec<_> = self.run(&LIST.replace("DEPTH", depth), inputs)?
+                                    .into_iter()
+                                    .filter(|line| {
+                                        line.starts_with('=') ||
+                                        options.accepts(Path::new(&line[1..]))
+                                    })
+                                    .map(|line| PathBuf::from(&line[1..]))
+                                    .collect();
+        files.sort();
+        files.dedup();
+        if files.is_empty() {
+            return Ok(files);
+        }
+
+        let names: Vec<_> = files.iter().map(|f| f.to_string_lossy()).collect();
+        let mut heads: Vec<(PathBuf, Vec<String>)> = Vec::new();
+        for line in self.run(&HEADS.replace("LINES", &HEAD_LINES.to_string()), &names)? {
+            if let Some(path) = line.strip_prefix('\u{1e}') {
+                heads.push((PathBuf::from(path), Vec::new()));
+            } else if let Some(head) = heads.last_mut() {
+                head.1.push(line);
+            }
+        }
+        let firsts: Vec<_> = files.iter()
+                                  .map(|f| {
+                                      heads.iter()
+                                           .find(|h| h.0 == *f)
+                                           .and_then(|h| head_timestamp(h.1.iter().cloned()))
+                                  })
+                                  .collect();
+        Ok(sorted_by(&files, &firsts, &options.rotation))
+    }
+
+    /// streams the file, the transfer stops when the reader is dropped
+    pub fn open(&self, path: &Path) -> io::Result<RemoteReader> {
+        let mut child = self.spawn("exec cat -- \"$1\"", &[path.to_string_lossy()])?;
+        let stdout = child.stdout.take().expect("piped stdout");
+        Ok(RemoteReader {
+            child,
+            reader: BufReader::new(stdout),
+        })
+    }
+
+    pub fn size(&self, path: &Path) -> u64 {
+        self.run("wc -c < \"$1\"", &[path.to_string_lossy()])
+            .ok()
+            .and_then(|lines| lines.first().and_then(|s| s.trim().parse().ok()))
+            .unwrap_or(0)
+    }
+}
+
+/// `RemoteReader` reads the output of the command streaming a file.
+pub struct RemoteReader {
+    child: Child,
+    reader: BufReader<ChildStdout>,
+}
+
+impl Read for RemoteReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl BufRead for RemoteReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.reader.consume(amount)
+    }
+}
+
+impl Drop for RemoteReader {
+    /// stops the transfer if the file was not read to the end
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Source {
+    /// the log files of the inputs, newest first, see `list_files`
+    pub fn list(&self, inputs: &[String], options: &InputOptions) -> io::Result<Vec<PathBuf>> {
+        match *self {
+            Source::Local => Ok(list_files(inputs, options)),
+            Source::Remote(ref remote) => remote.list(inputs, options),
+        }
+    }
+
+    pub fn open(&self, path: &Path) -> io::Result<Box<dyn BufRead>> {
+        Ok(match *self {
+            Source::Local => Box::new(BufReader::new(File::open(path)?)),
+            Source::Remote(ref remote) => Box::new(remote.open(path)?),
+        })
+    }
+
+    /// the size of the file in bytes, 0 if unknown
+    pub fn size(&self, path: &Path) -> u64 {
+        match *self {
+            Source::Local => fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+            Source::Remote(ref remote) => remote.size(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glob::Pattern;
+    use input::tests::create_logs;
+
+    fn stand_in() -> Remote {
+        Remote::new(vec!["sh".to_owned(), "-c".to_owned()])
+    }
+
+    #[test]
+    fn can_quote() {
+        assert_eq!(quote("a b"), "'a b'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn can_list_like_local() {
+        let dir = create_logs("tc_tool_remote_list",
+                              &[("a/tc.log", "2015-11-09 03:00:00,001 INFO"),
+                                ("a/tc.log.1", "2015-11-09 01:00:00,001 INFO"),
+                                ("b/it's tc.log", "2015-11-09 02:00:00,001 INFO"),
+                                ("b/sub/tc.log", "2015-11-09 04:00:00,001 INFO"),
+                                ("b/tc.gz", "2015-11-09 05:00:00,001 INFO")]);
+        let inputs = [dir.join("a/tc.log*").to_str().unwrap().to_owned(),
+                      dir.join("b").to_str().unwrap().to_owned()];
+        let remote = Source::Remote(stand_in());
+        for options in &[InputOptions {
+                             exclude: vec![Pattern::new("*.gz").unwrap()],
+                             ..InputOptions::default()
+                         },
+                         InputOptions {
+                             recursive: true,
+                             ..InputOptions::default()
+                         }] {
+            let files = remote.list(&inputs, options).unwrap();
+            assert_eq!(files, Source::Local.list(&inputs, options).unwrap());
+            assert!(!files.is_empty());
+        }
+        let path = dir.join("b/it's tc.log");
+        assert_eq!(remote.size(&path), 29);
+        let mut lines = remote.open(&path).unwrap().lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "2015-11-09 02:00:00,001 INFO");
+        assert!(lines.next().is_none());
+
+        assert!(remote.list(&[dir.join("none*").to_str().unwrap().to_owned()],
+                            &InputOptions::default())
+                      .unwrap()
+                      .is_empty());
+    }
+
+    #[test]
+    fn can_fail_listing() {
+        let failing = Remote::new(vec!["false".to_owned()]);
+        assert!(failing.list(&["*.log".to_owned()], &InputOptions::default()).is_err());
+        let missing = Remote::new(vec!["tc_tool_no_such_command".to_owned()]);
+        assert!(missing.list(&["*.log".to_owned()], &InputOptions::default()).is_err());
+    }
+
+    #[test]
+    fn can_stop_streaming() {
+        let text = "2015-11-09 03:00:00,001 INFO\n".repeat(100_000);
+        let dir = create_logs("tc_tool_remote_stop", &[("tc.log", &text)]);
+        let mut reader = stand_in().open(&dir.join("tc.log")).unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "2015-11-09 03:00:00,001 INFO\n");
+        // the transfer is stopped, not read to the end
+        drop(reader);
+    }
+}
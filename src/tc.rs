@@ -1,245 +1,807 @@
-use glob::glob;
-use regex::Regex;
-use std::io::{BufReader, BufRead};
-use std::path::PathBuf;
-use std::fs::File;
+use regex::RegexSet;
+use std::io::BufRead;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
 
 use logparser::*;
+use logresult::{Columns, ResultOptions};
+use output::Output;
+use history::History;
+use input::InputOptions;
+use source::Source;
+use listener::Message;
+use explain::Explain;
+use error::*;
+use filter::Filter;
+use logstat::Stat;
+use config::{self, DEFAULT_COUNT};
+use template::{self, Template};
 
 pub struct TcTool<'a> {
-    name: &'a str,
-    path: &'a str,
+    name: String,
+    /// files, directories or glob patterns of the log files
+    paths: Vec<String>,
+    input: InputOptions,
+    source: Source,
     pattern: LogParser<'a>,
     count: usize,
 }
 
 impl<'a> TcTool<'a> {
-    pub fn new_hour(count: usize, prod: bool) -> TcTool<'a> {
+    /// `count` is how many hours/batches to read, the older files are skipped once reached.
+    pub fn new(name: String,
+               paths: Vec<String>,
+               pattern: LogParser<'a>,
+               count: usize)
+               -> TcTool<'a> {
         TcTool {
-            name: "Hour",
-            path: if prod {
-                "xxxx/hour_prod.log*"
-            } else {
-                "xxxx/hour.log*"
-            },
-            pattern: LogParser::new(Regex::new(r"docWriteTime=([^}]+)}").unwrap()),
-
-            count: count,
+            name,
+            paths,
+            input: InputOptions::default(),
+            source: Source::Local,
+            pattern,
+            count,
         }
     }
 
+    pub fn from_template(template: &Template, count: usize, prod: bool) -> TcTool<'a> {
+        TcTool::new(template.name.to_owned(),
+                    vec![template.path(prod).to_owned()],
+                    template.parser(),
+                    count)
+    }
+
+    pub fn new_hour(count: usize, prod: bool) -> TcTool<'a> {
+        TcTool::from_template(template::find("Hour").unwrap(), count, prod)
+    }
 
     pub fn new_batch(count: usize, prod: bool) -> TcTool<'a> {
-        TcTool {
-            name: "Batch",
-            path: if prod {
-                "xxx/prod/logs/batch.log*"
-            } else {
-                "xxx/test/logs/batch.log*"
-            },
-            pattern: LogParser::new_batch("committed", Regex::new(r"Context contains (\d+)").ok()),
-            count: count,
-        }
-    }
-
-    /// sort the path base on extension. if no extension then assume it as 0
-    /// for example, make sure the file follow below order
-    /// tc.log
-    /// tc.log.1
-    /// tc.log.2
-    /// ...
-    /// tc.log.10
-    fn sorted_path(paths: &[PathBuf]) -> Vec<PathBuf> {
-        let mut paths_new: Vec<_> = paths.iter()
-                                         .map(|name| {
-                                             let ext: usize = match name.extension() {
-                                                 Some(ex) => {
-                                                     ex.to_str()
-                                                       .and_then(|m| m.parse::<usize>().ok())
-                                                       .unwrap_or(0)
-                                                 }
-                                                 None => 0,
-                                             };
-                                             (name, ext)
-                                         })
-                                         .collect();
-        paths_new.sort_by(|a, b| a.1.cmp(&b.1));
-        paths_new.iter().map(|a| a.0).cloned().collect()
-    }
-    /// Process files which matched the path pattern. for example: directory/file*
+        TcTool::from_template(template::find("Batch").unwrap(), count, prod)
+    }
+
+    /// Replaces the log files of the monitor, see `list_files` for the inputs.
+    pub fn set_inputs(&mut self, paths: Vec<String>, input: InputOptions) {
+        self.paths = paths;
+        self.input = input;
+    }
+
+    /// Reads the log files from the source instead of the local file system.
+    pub fn set_source(&mut self, source: Source) {
+        self.source = source;
+    }
+
+    /// the files of the monitor, newest first
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.source.list(&self.paths, &self.input).expect("Failed to list log files.")
+    }
+
+    /// Process files which matched the paths, newest first. for example: directory/file*
     pub fn process_directory(&mut self) {
-        let files: Vec<_> = glob(self.path).unwrap().filter_map(|r| r.ok()).collect();
-        let files = Self::sorted_path(&files);
+        let files = self.files();
 
         for name in files {
-            let file = File::open(&name).expect("Failed to open log file.");
-            for line in BufReader::new(file).lines().filter_map(|line| line.ok()) {
-                self.pattern.process_line(&line);
-            }
+            let file = self.source.open(&name).expect("Failed to open log file.");
             // we have enough samples, stop!
-            if self.pattern.wrap_up_file() > self.count {
+            if self.process_reader(file) > self.count {
                 return;
             }
         }
     }
 
-    pub fn print_result(&self) {
-        self.pattern.print_result(self.name);
+    /// Process a stream of log lines, for example stdin or the output of `ssh host cat`.
+    /// The stream is handled as a single log file.
+    /// Returns the result count, same as `LogParser::wrap_up_file`.
+    pub fn process_reader<R: BufRead>(&mut self, reader: R) -> usize {
+        for line in reader.lines().map_while(|line| line.ok()) {
+            self.pattern.process_line(&line);
+        }
+        self.pattern.wrap_up_file()
+    }
+
+    /// Same as `process_directory`, also explains which files are read and how their lines are
+    /// handled.
+    pub fn explain_directory(&mut self, explain: &mut Explain) {
+        let files = self.files();
+        if files.is_empty() {
+            explain.note(format!("no file found for {}", self.paths.join(" ")));
+        }
+        for (order, name) in files.iter().enumerate() {
+            explain.file(order + 1, name, self.source.size(name));
+        }
+
+        for name in files {
+            let file = self.source.open(&name).expect("Failed to open log file.");
+            if self.explain_reader(file, explain) > self.count {
+                explain.note(format!("enough samples after {}, skipped the older files",
+                                     name.display()));
+                return;
+            }
+        }
+    }
+
+    /// Same as `process_reader`, also explains how every line is handled.
+    pub fn explain_reader<R: BufRead>(&mut self, reader: R, explain: &mut Explain) -> usize {
+        for (number, line) in reader.lines().map_while(|line| line.ok()).enumerate() {
+            let explanation = self.pattern.explain_line(&line);
+            explain.record(number + 1, &line, &explanation);
+        }
+        self.pattern.wrap_up_file()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_batch(&self) -> bool {
+        self.pattern.is_batch()
+    }
+
+    /// the optional text columns of the captures of the monitor
+    pub fn columns(&self) -> Columns {
+        self.pattern.columns()
+    }
+
+    #[cfg(test)]
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    #[cfg(test)]
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn into_parser(self) -> LogParser<'a> {
+        self.pattern
+    }
+
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.pattern.set_filter(filter);
+    }
+
+    pub fn set_options(&mut self, options: ResultOptions) {
+        self.pattern.set_options(options);
+    }
+
+    /// the stat of every hour/batch, oldest first
+    pub fn stats(&self) -> Vec<(usize, &Stat)> {
+        self.pattern.stats()
+    }
+
+    pub fn print_result(&self, out: &mut Output) {
+        self.pattern.print_result(&self.name, out);
+    }
+
+    pub fn record_history(&self, history: &mut History) {
+        self.pattern.record_history(&self.name, history);
+    }
+}
+
+/// Returns the monitors of the config: "hour", the name of a template or a config file, see
+/// `config::parse`. The named captures of their matchers are validated.
+pub fn monitors<'a>(config: &str, prod: bool) -> Result<Vec<TcTool<'a>>> {
+    let monitors = match config {
+        "hour" => {
+            vec![TcTool::new_hour(DEFAULT_COUNT, prod),
+                 TcTool::new_batch(DEFAULT_COUNT, prod)]
+        }
+        path if Path::new(path).is_file() => {
+            let text = fs::read_to_string(path)
+                           .map_err(|e| LogError::InvalidConfig(e.to_string()))?;
+            config::parse(&text, prod)?
+        }
+        name => {
+            match template::find(name) {
+                Some(t) => vec![TcTool::from_template(t, DEFAULT_COUNT, prod)],
+                None => return Err(LogError::MissingConfig),
+            }
+        }
+    };
+    for tc in &monitors {
+        tc.pattern.validate()?;
+    }
+    Ok(monitors)
+}
+
+/// Feeds every line of the stream to all the monitors, so a stream like stdin which can only be
+/// read once is shared by them. The stream is handled as a single log file.
+pub fn process_shared_reader<R: BufRead>(monitors: &mut [TcTool], reader: R) {
+    for line in reader.lines().map_while(|line| line.ok()) {
+        for tc in monitors.iter_mut() {
+            tc.pattern.process_line(&line);
+        }
+    }
+    for tc in monitors.iter_mut() {
+        tc.pattern.wrap_up_file();
+    }
+}
+
+/// `Dispatcher` decides which monitors may count a line, with a single `RegexSet` of the patterns
+/// of all the monitors.
+struct Dispatcher {
+    set: RegexSet,
+    /// the monitor of every pattern in the set
+    owners: Vec<usize>,
+    /// the monitors which can't be expressed by patterns, they get every line
+    always: Vec<usize>,
+}
+
+impl Dispatcher {
+    fn new(monitors: &[TcTool]) -> Dispatcher {
+        let mut patterns = Vec::new();
+        let mut owners = Vec::new();
+        let mut always = Vec::new();
+        for (i, tc) in monitors.iter().enumerate() {
+            match tc.pattern.patterns() {
+                Some(p) => {
+                    owners.extend(p.iter().map(|_| i));
+                    patterns.extend(p);
+                }
+                None => always.push(i),
+            }
+        }
+        Dispatcher {
+            set: RegexSet::new(patterns).expect("Invalid matcher regex"),
+            owners,
+            always,
+        }
+    }
+
+    /// sets the monitors interested in the line, `matched` is a buffer for the set
+    fn interested(&self, line: &str, matched: &mut [bool], interested: &mut [bool]) {
+        for m in matched.iter_mut().chain(interested.iter_mut()) {
+            *m = false;
+        }
+        self.set.read_matches_at(matched, line, 0);
+        for (owner, _) in self.owners.iter().zip(matched.iter()).filter(|m| *m.1) {
+            interested[*owner] = true;
+        }
+        for i in &self.always {
+            interested[*i] = true;
+        }
+    }
+}
+
+/// Reads the files of the source once for all the monitors, `files` is the order of all of them
+/// and `lists` are the files of every monitor, see `merge_files`. Every line only goes to the
+/// monitors which read the file and may count it. A monitor stops at its own count, same as
+/// `process_directory`, the files are read until all of them have enough samples.
+pub fn process_shared_files(monitors: &mut [TcTool],
+                            source: &Source,
+                            files: &[PathBuf],
+                            lists: &[Vec<PathBuf>]) {
+    let dispatcher = Dispatcher::new(monitors);
+    let mut matched = vec![false; dispatcher.owners.len()];
+    let mut interested = vec![false; monitors.len()];
+    let mut done = vec![false; monitors.len()];
+
+    for name in files {
+        let reading: Vec<_> = lists.iter()
+                                   .zip(&done)
+                                   .map(|(list, done)| !done && list.contains(name))
+                                   .collect();
+        if !reading.iter().any(|r| *r) {
+            continue;
+        }
+        let file = source.open(name).expect("Failed to open log file.");
+        for line in file.lines().map_while(|line| line.ok()) {
+            dispatcher.interested(&line, &mut matched, &mut interested);
+            for (i, tc) in monitors.iter_mut().enumerate() {
+                if interested[i] && reading[i] {
+                    tc.pattern.process_line(&line);
+                }
+            }
+        }
+        for (i, tc) in monitors.iter_mut().enumerate() {
+            if reading[i] && tc.pattern.wrap_up_file() > tc.count {
+                done[i] = true;
+            }
+        }
+        // we have enough samples, stop!
+        if done.iter().all(|d| *d) {
+            return;
+        }
+    }
+}
+
+/// Merges two newest first lists of files into one which keeps the order of both, None if they
+/// read their common files in a different order, for example with another `--rotation`.
+fn merge_files(a: &[PathBuf], b: &[PathBuf]) -> Option<Vec<PathBuf>> {
+    let (mut i, mut j) = (0, 0);
+    let mut merged = Vec::with_capacity(a.len() + b.len());
+    while i < a.len() || j < b.len() {
+        if j < b.len() && !a.contains(&b[j]) {
+            merged.push(b[j].clone());
+            j += 1;
+        } else if i < a.len() && !b.contains(&a[i]) {
+            merged.push(a[i].clone());
+            i += 1;
+        } else if i < a.len() && j < b.len() && a[i] == b[j] {
+            merged.push(a[i].clone());
+            i += 1;
+            j += 1;
+        } else {
+            return None;
+        }
+    }
+    Some(merged)
+}
+
+/// `Router` feeds the messages of the listener to the monitors, which keep their result between
+/// the messages. The messages of a routed program only go to the monitors of its routes, the
+/// others go to the monitors which may count them, same as `process_shared_files`.
+pub struct Router<'a> {
+    monitors: Vec<TcTool<'a>>,
+    /// (program, monitor)
+    routes: Vec<(String, usize)>,
+    dispatcher: Dispatcher,
+    matched: Vec<bool>,
+    interested: Vec<bool>,
+}
+
+impl<'a> Router<'a> {
+    /// `routes` are "PROGRAM=MONITOR", for example "publisher=V1Publisher"
+    pub fn new(monitors: Vec<TcTool<'a>>, routes: &[&str]) -> Result<Router<'a>> {
+        let routes = routes.iter()
+                           .map(|route| {
+                               let invalid = || LogError::InvalidRoute(route.to_string());
+                               let (program, name) = route.split_once('=').ok_or_else(invalid)?;
+                               monitors.iter()
+                                       .position(|tc| tc.name == name)
+                                       .map(|i| (program.to_owned(), i))
+                                       .ok_or_else(invalid)
+                           })
+                           .collect::<Result<Vec<_>>>()?;
+        let dispatcher = Dispatcher::new(&monitors);
+        Ok(Router {
+            matched: vec![false; dispatcher.owners.len()],
+            interested: vec![false; monitors.len()],
+            monitors,
+            routes,
+            dispatcher,
+        })
+    }
+
+    pub fn process(&mut self, message: &Message) {
+        let app = message.app.as_ref();
+        if app.is_some() && self.routes.iter().any(|r| Some(&r.0) == app) {
+            for interested in self.interested.iter_mut() {
+                *interested = false;
+            }
+            for route in self.routes.iter().filter(|r| Some(&r.0) == app) {
+                self.interested[route.1] = true;
+            }
+        } else {
+            self.dispatcher.interested(&message.line, &mut self.matched, &mut self.interested);
+        }
+        for (tc, _) in self.monitors.iter_mut().zip(&self.interested).filter(|m| *m.1) {
+            tc.pattern.process_line(&message.line);
+        }
+    }
+
+    /// wrap_up_interval ends an interval of the stream, every monitor keeps the hours/batches
+    /// of its count, same as reading files.
+    pub fn wrap_up_interval(&mut self) {
+        for tc in self.monitors.iter_mut() {
+            tc.pattern.wrap_up_interval(tc.count + 1);
+        }
+    }
+
+    pub fn monitors(&self) -> &[TcTool<'a>] {
+        &self.monitors
     }
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//     use test::Bencher;
-//     use std::thread;
-
-//     fn process_line_incorrect_tester(tc: &mut TcTool) {
-//         let lines: Vec<_> = vec![
-//                 "incorrect line",
-//                 "",
-//                 "2015-09-11 09:28:49,842 aaaaaaaaaaaaaaaaaa timestamp=aaaa",
-//                 "2015-09-11 09:28:49,842 aaaaaaaaaaaaaaaaaa docWriteTime=aaaa",
-//                 "2015-09-11 09:28:49,842 aaaaaaaaaaaaaaaaaa DocWriteTime=aaaa",
-//             ];
-
-//         for line in lines {
-//             let (pub_time, watermark, _) = tc.pattern.extract_info(&line);
-//             assert_eq!(pub_time, None);
-//             assert_eq!(watermark, None);
-//         }
-//     }
-
-//     #[bench]
-//     fn bench_process_line_v1_publisher(b: &mut Bencher) {
-//         let mut tc = TcTool::new_v1_publisher(6, false);
-
-//         let line = "2015-11-08 09:07:54,679 JMS DocWriteTime=20151028 07:17:17,";
-
-//         b.iter(|| {
-//             let (pub_time, watermark, _) = tc.pattern.extract_info(&line);
-//             assert_eq!(pub_time, Some("2015-11-08 09:07:54"));
-//             assert_eq!(watermark, Some("20151028 07:17:17"));
-
-//             process_line_incorrect_tester(&mut tc);
-//         });
-//     }
-
-//     #[bench]
-//     fn bench_process_line_ng_consumer(b: &mut Bencher) {
-//         let mut tc = TcTool::new_ng_consumer(6, false);
-
-//         let line = "2015-09-11 09:28:49,842 INFO timestamp=Fri Sep 11 09:28:49 BST \
-//                     2015eventId=45139252}";
-//         b.iter(|| {
-//             let (pub_time, watermark, _) = tc.pattern.extract_info(&line);
-//             assert_eq!(pub_time, Some("2015-09-11 09:28:49"));
-//             assert_eq!(watermark, Some("Fri Sep 11 09:28:49 BST 2015"));
-
-//             process_line_incorrect_tester(&mut tc);
-//         });
-//     }
-
-//     #[bench]
-//     fn bench_process_line_ng_publisher(b: &mut Bencher) {
-//         let mut tc = TcTool::new_ng_publisher(6, false);
-
-//         let line = "2015-09-09 02:35:01,024 =, docWriteTime=2015-09-09 01:35:03}, ";
-//         b.iter(|| {
-//             let (pub_time, watermark, _) = tc.pattern.extract_info(&line);
-//             assert_eq!(pub_time, Some("2015-09-09 02:35:01"));
-//             assert_eq!(watermark, Some("2015-09-09 01:35:03"));
-
-//             process_line_incorrect_tester(&mut tc);
-//         });
-//     }
-
-//     #[bench]
-//     fn bench_process_line_ng_trimmer(b: &mut Bencher) {
-//         let mut tc = TcTool::new_ng_trimmer(6, false);
-
-//         let line = "2015-09-10 21:06:34,594 INFO    - committed deletes to disk cache";
-//         b.iter(|| {
-//             let (pub_time, watermark, _) = tc.pattern.extract_info(&line);
-//             assert_eq!(pub_time, Some("2015-09-10 21:06:34"));
-//             assert_eq!(watermark, None);
-
-//             process_line_incorrect_tester(&mut tc);
-//         });
-//     }
-
-
-//     #[bench]
-//     #[ignore]
-//     fn bench_tc_v1_process(b: &mut Bencher) {
-//         b.iter(|| {
-//             let mut publisher = TcTool::new_v1_publisher(6, false);
-//             publisher.process_directory();
-//         });
-//     }
-
-//     #[bench]
-//     #[ignore]
-//     fn bench_tc_ng_process(b: &mut Bencher) {
-//         b.iter(|| {
-//             let mut consumer = TcTool::new_ng_consumer(6, false);
-//             consumer.process_directory();
-//         });
-//     }
-
-//     #[bench]
-//     #[ignore]
-//     fn bench_tc_ng_trimmer(b: &mut Bencher) {
-//         b.iter(|| {
-//             let mut trimmer = TcTool::new_ng_trimmer(6, false);
-//             trimmer.process_directory();
-//         });
-//     }
-
-
-//     #[bench]
-//     #[ignore]
-//     fn bench_process_two(b: &mut Bencher) {
-//         b.iter(|| {
-//             let mut publisher = TcTool::new_v1_publisher(6, false);
-//             let mut ng_consumer = TcTool::new_ng_consumer(6, false);
-
-//             let handle_pub = thread::spawn(move || {
-//                 publisher.process_directory();
-//             });
-
-//             let handle_consumer = thread::spawn(move || {
-//                 ng_consumer.process_directory();
-//             });
-
-//             handle_pub.join().unwrap();
-//             handle_consumer.join().unwrap();
-//         });
-//     }
-
-//     #[bench]
-//     #[ignore]
-//     fn bench_process_three(b: &mut Bencher) {
-//         b.iter(|| {
-//             let mut ng_pub = TcTool::new_ng_publisher(6, false);
-//             let mut ng_con = TcTool::new_ng_consumer(6, false);
-//             let mut v1_pub = TcTool::new_v1_publisher(6, false);
-
-//             let h_pub = thread::spawn(move || {
-//                 ng_pub.process_directory();
-//             });
-
-//             let h_con = thread::spawn(move || {
-//                 ng_con.process_directory();
-//             });
-
-//             let h_v1_pub = thread::spawn(move || {
-//                 v1_pub.process_directory();
-//             });
-
-//             h_pub.join().unwrap();
-//             h_con.join().unwrap();
-//             h_v1_pub.join().unwrap();
-//         });
-//     }
-// }
+/// Processes the files of all the monitors. The monitors reading some of the same files of the
+/// same source are grouped to share a single pass over them, each group runs in its own thread.
+/// Returns the monitors in the same order.
+pub fn process_all(monitors: Vec<TcTool<'static>>) -> Vec<TcTool<'static>> {
+    let mut files: Vec<(Source, Vec<PathBuf>)> = Vec::new();
+    let mut groups: Vec<Vec<(usize, TcTool, Vec<PathBuf>)>> = Vec::new();
+    for (i, tc) in monitors.into_iter().enumerate() {
+        let tc_files = tc.files();
+        let shared = files.iter().enumerate().find_map(|(g, (source, files))| {
+            if *source != tc.source || !files.iter().any(|f| tc_files.contains(f)) {
+                return None;
+            }
+            merge_files(files, &tc_files).map(|merged| (g, merged))
+        });
+        match shared {
+            Some((g, merged)) => {
+                files[g].1 = merged;
+                groups[g].push((i, tc, tc_files));
+            }
+            None => {
+                files.push((tc.source.clone(), tc_files.clone()));
+                groups.push(vec![(i, tc, tc_files)]);
+            }
+        }
+    }
+
+    let handlers: Vec<_> = files.into_iter()
+                                .zip(groups)
+                                .map(|((source, files), group)| {
+                                    thread::spawn(move || {
+                                        let mut order = Vec::new();
+                                        let mut monitors = Vec::new();
+                                        let mut lists = Vec::new();
+                                        for (i, tc, list) in group {
+                                            order.push(i);
+                                            monitors.push(tc);
+                                            lists.push(list);
+                                        }
+                                        if monitors.len() == 1 {
+                                            monitors[0].process_directory();
+                                        } else {
+                                            process_shared_files(&mut monitors,
+                                                                 &source,
+                                                                 &files,
+                                                                 &lists);
+                                        }
+                                        order.into_iter().zip(monitors).collect::<Vec<_>>()
+                                    })
+                                })
+                                .collect();
+
+    let mut monitors: Vec<_> = handlers.into_iter().flat_map(|h| h.join().unwrap()).collect();
+    monitors.sort_by_key(|m| m.0);
+    monitors.into_iter().map(|m| m.1).collect()
+}
+
+/// Same as `process_shared_reader`, also explains how every line is handled by every monitor.
+pub fn explain_shared_reader<R: BufRead>(monitors: &mut [TcTool],
+                                         explains: &mut [Explain],
+                                         reader: R) {
+    for (number, line) in reader.lines().map_while(|line| line.ok()).enumerate() {
+        for (tc, explain) in monitors.iter_mut().zip(explains.iter_mut()) {
+            let explanation = tc.pattern.explain_line(&line);
+            explain.record(number + 1, &line, &explanation);
+        }
+    }
+    for tc in monitors.iter_mut() {
+        tc.pattern.wrap_up_file();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::process;
+    use output::{Format, Output};
+    use test::Bencher;
+    use source::Remote;
+
+    const LINES: &str = "2015-11-09 01:59:03,100 INFO {docWriteTime=2015-11-09 01:58:00}
+2015-11-09 02:00:00,000 INFO Context contains 3 items
+2015-11-09 02:01:03,100 INFO {docWriteTime=2015-11-09 02:01:00}
+2015-11-09 02:02:00,000 INFO committed
+2015-11-09 02:03:03,100 INFO {docWriteTime=2015-11-09 02:02:03}
+";
+
+    fn rows(tc: &TcTool) -> Vec<String> {
+        let mut out = Output::new(Format::Json);
+        tc.print_result(&mut out);
+        out.lines
+    }
+
+    #[test]
+    fn can_process_reader() {
+        let mut hour = TcTool::new_hour(6, false);
+        assert_eq!(hour.process_reader(Cursor::new(LINES)), 2);
+
+        let rows = rows(&hour);
+        assert_eq!(rows.len(), 1);
+        assert!(rows[0].contains(r#""last_sample_time":"2015-11-09 02:03:03","total":0,"done":2,"#));
+    }
+
+    #[test]
+    fn can_explain_reader() {
+        let mut batch = TcTool::new_batch(6, false);
+        let mut explain = Explain::new(batch.name(), 5);
+        batch.explain_reader(Cursor::new(LINES), &mut explain);
+        explain.summary();
+
+        assert_eq!(explain.lines.last().unwrap(),
+                   "Batch: 5 lines, 2 accepted, 3 no match, 0 bad timestamp, 0 bad count, \
+                    0 duplicate");
+        assert!(explain.lines.iter().any(|l| {
+            l == "  line 2 [accepted] matcher=batch captures=[total=\"3\"] \
+                  timestamp=\"2015-11-09 02:00:00\" time=2015-11-09 02:00:00 bucket=20151109020000"
+        }));
+        assert!(explain.lines.iter().any(|l| l.starts_with("  line 4 [accepted] matcher=line")));
+
+        let mut hour = TcTool::new_hour(6, false);
+        let mut explain = Explain::new(hour.name(), 5);
+        hour.explain_reader(Cursor::new("2015-11-09 02:01:03 {docWriteTime=x}\n"), &mut explain);
+        assert_eq!(explain.lines[1],
+                   "  line 1 [bad timestamp] matcher=line captures=[watermark=\"x\"] \
+                    timestamp=\"\" time=- bucket=-");
+    }
+
+    #[test]
+    fn can_dispatch_lines() {
+        let mut monitors = vec![TcTool::new_hour(6, false), TcTool::new_batch(6, false)];
+        monitors[1].set_filter("NOT \"dry-run\"".parse().unwrap());
+        let dispatcher = Dispatcher::new(&monitors);
+        let mut matched = vec![false; dispatcher.owners.len()];
+        let mut interested = vec![false; 2];
+
+        dispatcher.interested("2015-11-09 02:01:03,100 {docWriteTime=2015-11-09 02:01:00}",
+                              &mut matched,
+                              &mut interested);
+        assert_eq!(interested, [true, false]);
+        dispatcher.interested("2015-11-09 02:00:00,000 INFO Context contains 3 items",
+                              &mut matched,
+                              &mut interested);
+        assert_eq!(interested, [false, true]);
+        dispatcher.interested("nothing", &mut matched, &mut interested);
+        assert_eq!(interested, [false, false]);
+
+        // a filter or structured matcher can't be checked by the regex set
+        let filter = "\"a\" OR \"b\"".parse::<Filter>().unwrap();
+        let monitors = vec![TcTool { pattern: LogParser::new(filter), ..TcTool::new_hour(6, false) }];
+        let dispatcher = Dispatcher::new(&monitors);
+        dispatcher.interested("nothing", &mut matched, &mut interested[..1]);
+        assert!(interested[0]);
+    }
+
+    #[test]
+    fn can_process_shared_files() {
+        let path = ::std::env::temp_dir().join(format!("tc_tool_shared_{}.log", process::id()));
+        fs::write(&path, LINES).unwrap();
+        let inputs = vec![path.to_str().unwrap().to_owned()];
+        let mut monitors = vec![TcTool::new_hour(6, false),
+                                TcTool::new_batch(6, false),
+                                TcTool::new_hour(6, false)];
+        monitors[2].name = "Other".to_owned();
+        monitors[2].set_inputs(vec!["missing/*.log".to_owned()], InputOptions::default());
+        for tc in &mut monitors[..2] {
+            tc.set_inputs(inputs.clone(), InputOptions::default());
+        }
+
+        let monitors = process_all(monitors);
+        assert_eq!(monitors.iter().map(|tc| tc.name()).collect::<Vec<_>>(),
+                   ["Hour", "Batch", "Other"]);
+        assert!(rows(&monitors[0])[0].contains(r#""done":2,"#));
+        assert!(rows(&monitors[1])[0].contains(r#""total":3,"done":1,"#));
+        assert!(rows(&monitors[2]).is_empty());
+    }
+
+    #[test]
+    fn can_merge_files() {
+        let paths = |names: &str| names.split(' ').map(PathBuf::from).collect::<Vec<_>>();
+        assert_eq!(merge_files(&paths("b a"), &paths("c b")), Some(paths("c b a")));
+        assert_eq!(merge_files(&paths("c a"), &paths("c b a")), Some(paths("c b a")));
+        assert_eq!(merge_files(&paths("b a"), &paths("a b")), None);
+    }
+
+    #[test]
+    fn can_share_some_files() {
+        let dir = ::std::env::temp_dir();
+        let shared = dir.join(format!("tc_tool_some_shared_{}.log", process::id()));
+        let older = dir.join(format!("tc_tool_some_older_{}.log", process::id()));
+        fs::write(&shared, LINES).unwrap();
+        fs::write(&older, LINES.replace("2015-11-09", "2015-11-08")).unwrap();
+        let mut monitors = vec![TcTool::new_hour(6, false), TcTool::new_hour(6, false)];
+        monitors[1].name = "Other".to_owned();
+        monitors[0].set_inputs(vec![shared.to_str().unwrap().to_owned()], InputOptions::default());
+        monitors[1].set_inputs(vec![shared.to_str().unwrap().to_owned(),
+                                    older.to_str().unwrap().to_owned()],
+                               InputOptions::default());
+
+        let monitors = process_all(monitors);
+        assert_eq!(rows(&monitors[0]).len(), 1);
+        assert_eq!(rows(&monitors[1]).len(), 3);
+        assert_eq!(rows(&monitors[0])[0], rows(&monitors[1])[0].replace("Other", "Hour"));
+    }
+
+    #[test]
+    fn can_process_remote_files() {
+        let path = ::std::env::temp_dir().join(format!("tc_tool_remote_{}.log", process::id()));
+        fs::write(&path, LINES).unwrap();
+        let inputs = vec![path.to_str().unwrap().to_owned()];
+        let stand_in = Source::Remote(Remote::new(vec!["sh".to_owned(), "-c".to_owned()]));
+        let mut monitors = vec![TcTool::new_hour(6, false),
+                                TcTool::new_batch(6, false),
+                                TcTool::new_hour(6, false)];
+        monitors[2].name = "Local".to_owned();
+        for tc in &mut monitors {
+            tc.set_inputs(inputs.clone(), InputOptions::default());
+        }
+        for tc in &mut monitors[..2] {
+            tc.set_source(stand_in.clone());
+        }
+
+        let monitors = process_all(monitors);
+        assert!(rows(&monitors[0])[0].contains(r#""done":2,"#));
+        assert!(rows(&monitors[1])[0].contains(r#""total":3,"done":1,"#));
+        assert_eq!(rows(&monitors[0])[0], rows(&monitors[2])[0].replace("Local", "Hour"));
+    }
+
+    #[test]
+    fn can_route_messages() {
+        let mut other = TcTool::new_hour(6, false);
+        other.name = "Other".to_owned();
+        let monitors = vec![TcTool::new_hour(6, false), TcTool::new_batch(6, false), other];
+        for route in &["publisher", "publisher=Nothing"] {
+            assert!(Router::new(vec![TcTool::new_hour(6, false)], &[route]).is_err());
+        }
+        let mut router = Router::new(monitors, &["publisher=Other", "batch=Batch"]).unwrap();
+        for line in LINES.lines() {
+            let app = if line.contains("docWriteTime") { "publisher" } else { "batch" };
+            router.process(&Message {
+                app: Some(app.to_owned()),
+                line: line.to_owned(),
+            });
+        }
+        // not routed, by matcher
+        router.process(&Message {
+            app: None,
+            line: "2015-11-09 02:04:03,100 INFO {docWriteTime=2015-11-09 02:04:00}".to_owned(),
+        });
+
+        let monitors = router.monitors();
+        let done = |tc: &TcTool| tc.stats().iter().map(|s| (s.0, s.1.done)).collect::<Vec<_>>();
+        assert_eq!(done(&monitors[0]), [(2015110902, 1)]);
+        assert!(rows(&monitors[1])[0].contains(r#""total":3,"done":1,"#));
+        assert_eq!(done(&monitors[2]), [(2015110901, 1), (2015110902, 3)]);
+    }
+
+    #[test]
+    fn can_keep_count_while_listening() {
+        let monitors = vec![TcTool::new_hour(1, false), TcTool::new_batch(1, false)];
+        let mut router = Router::new(monitors, &[]).unwrap();
+        let mut process = |line: String| router.process(&Message { app: None, line });
+        // before the first batch start
+        process("2015-11-09 00:59:00,000 INFO committed".to_owned());
+        for hour in 1..4 {
+            process(format!("2015-11-09 0{}:00:00,000 INFO Context contains 3 items", hour));
+            process(format!("2015-11-09 0{}:01:00,000 INFO {{docWriteTime=x}}", hour));
+            process(format!("2015-11-09 0{}:02:00,000 INFO committed", hour));
+        }
+        router.wrap_up_interval();
+
+        let keys = |tc: &TcTool| tc.stats().iter().map(|s| s.0).collect::<Vec<_>>();
+        assert_eq!(keys(&router.monitors()[0]), [2015110902, 2015110903]);
+        assert_eq!(keys(&router.monitors()[1]), [20151109020000, 20151109030000]);
+        // the current batch goes on
+        router.process(&Message {
+            app: None,
+            line: "2015-11-09 03:03:00,000 INFO committed".to_owned(),
+        });
+        assert_eq!(router.monitors()[1].stats()[1].1.done, 2);
+    }
+
+    #[test]
+    fn can_share_reader() {
+        let mut monitors = vec![TcTool::new_hour(6, false), TcTool::new_batch(6, false)];
+        process_shared_reader(&mut monitors, Cursor::new(LINES));
+
+        assert!(rows(&monitors[0])[0].contains(r#""done":2,"#));
+        let batch = rows(&monitors[1]);
+        assert!(batch[0].contains(r#""last_sample_time":"2015-11-09 02:00:00","total":3,"done":1,"#));
+    }
+
+    fn process_line_incorrect_tester(tc: &TcTool) {
+        let lines = ["incorrect line",
+                     "",
+                     "2015-09-11 09:28:49,842 aaaaaaaaaaaaaaaaaa timestamp=aaaa",
+                     "2015-09-11 09:28:49,842 aaaaaaaaaaaaaaaaaa docWriteTime=aaaa",
+                     "2015-09-11 09:28:49,842 aaaaaaaaaaaaaaaaaa DocWriteTime=aaaa"];
+
+        for line in &lines {
+            assert_eq!(tc.pattern.extract_info(line), None);
+        }
+    }
+
+    /// the sample line of every template, with its time stamp and watermark
+    const SAMPLES: [(&str, &str, &str, Option<&str>); 4] =
+        [("V1Publisher",
+          "2015-11-08 09:07:54,679 JMS DocWriteTime=20151028 07:17:17,",
+          "2015-11-08 09:07:54",
+          Some("20151028 07:17:17")),
+         ("NgConsumer",
+          "2015-09-11 09:28:49,842 INFO timestamp=Fri Sep 11 09:28:49 BST 2015eventId=45139252}",
+          "2015-09-11 09:28:49",
+          Some("Fri Sep 11 09:28:49 BST 2015")),
+         ("NgPublisher",
+          "2015-09-09 02:35:01,024 =, docWriteTime=2015-09-09 01:35:03}, ",
+          "2015-09-09 02:35:01",
+          Some("2015-09-09 01:35:03")),
+         ("NgTrimmer",
+          "2015-09-10 21:06:34,594 INFO    - committed deletes to disk cache",
+          "2015-09-10 21:06:34",
+          None)];
+
+    #[test]
+    fn can_match_template_samples() {
+        for &(name, line, time, watermark) in &SAMPLES {
+            let tc = TcTool::from_template(template::find(name).unwrap(), 6, false);
+            tc.pattern.validate().unwrap();
+            let info = tc.pattern.extract_info(line).unwrap();
+            assert_eq!(info.time, Some(time));
+            assert_eq!(info.watermark, watermark);
+            process_line_incorrect_tester(&tc);
+        }
+        let consumer = monitors("ngconsumer", true).unwrap();
+        assert_eq!(consumer[0].paths, ["xxx/prod/logs/ng_consumer.log*"]);
+        let info = consumer[0].pattern.extract_info(SAMPLES[1].1).unwrap();
+        assert_eq!(info.id, Some("45139252"));
+        assert!(monitors("nothing", false).is_err());
+    }
+
+    #[test]
+    fn can_load_config_file() {
+        let path = ::std::env::temp_dir().join(format!("tc_tool_monitors_{}.conf", process::id()));
+        fs::write(&path, "[Trimmer]\ntemplate = NgTrimmer\ncount = 2\n").unwrap();
+        let monitors = monitors(path.to_str().unwrap(), false).unwrap();
+        assert_eq!(monitors[0].name(), "Trimmer");
+        assert_eq!(monitors[0].count, 2);
+
+        fs::write(&path, "[Bad]\nregex = (?P<unknown>x)\npath = a.log\n").unwrap();
+        assert!(super::monitors(path.to_str().unwrap(), false).is_err());
+    }
+
+    #[bench]
+    fn bench_process_line_v1_publisher(b: &mut Bencher) {
+        let mut tc = TcTool::from_template(template::find("V1Publisher").unwrap(), 6, false);
+        b.iter(|| tc.pattern.process_line(SAMPLES[0].1));
+    }
+
+    #[bench]
+    fn bench_process_line_ng_consumer(b: &mut Bencher) {
+        let mut tc = TcTool::from_template(template::find("NgConsumer").unwrap(), 6, false);
+        b.iter(|| tc.pattern.process_line(SAMPLES[1].1));
+    }
+
+    #[bench]
+    fn bench_process_line_ng_publisher(b: &mut Bencher) {
+        let mut tc = TcTool::from_template(template::find("NgPublisher").unwrap(), 6, false);
+        b.iter(|| tc.pattern.process_line(SAMPLES[2].1));
+    }
+
+    #[bench]
+    fn bench_process_line_ng_trimmer(b: &mut Bencher) {
+        let mut tc = TcTool::from_template(template::find("NgTrimmer").unwrap(), 6, false);
+        b.iter(|| tc.pattern.process_line(SAMPLES[3].1));
+    }
+
+    /// the monitor of the template, reading the test environment logs
+    fn monitor(name: &str) -> TcTool<'static> {
+        TcTool::from_template(template::find(name).unwrap(), 6, false)
+    }
+
+    #[bench]
+    #[ignore]
+    fn bench_tc_v1_process(b: &mut Bencher) {
+        b.iter(|| monitor("V1Publisher").process_directory());
+    }
+
+    #[bench]
+    #[ignore]
+    fn bench_tc_ng_process(b: &mut Bencher) {
+        b.iter(|| monitor("NgConsumer").process_directory());
+    }
+
+    #[bench]
+    #[ignore]
+    fn bench_tc_ng_trimmer(b: &mut Bencher) {
+        b.iter(|| monitor("NgTrimmer").process_directory());
+    }
+
+    #[bench]
+    #[ignore]
+    fn bench_process_two(b: &mut Bencher) {
+        b.iter(|| process_all(vec![monitor("V1Publisher"), monitor("NgConsumer")]));
+    }
+
+    #[bench]
+    #[ignore]
+    fn bench_process_three(b: &mut Bencher) {
+        b.iter(|| {
+            process_all(vec![monitor("NgPublisher"), monitor("NgConsumer"), monitor("V1Publisher")])
+        });
+    }
+}
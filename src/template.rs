@@ -0,0 +1,73 @@
+use regex::Regex;
+
+use logparser::LogParser;
+
+/// `Template` is a preset monitor, the matchers and the usual log files of a kind of log.
+pub struct Template {
+    pub name: &'static str,
+    /// regex of the counted lines
+    pub line: &'static str,
+    /// regex of the batch start lines, the counts are then by batch instead of by hour
+    pub batch: Option<&'static str>,
+    /// the log files in the test and the prod environment
+    pub paths: [&'static str; 2],
+}
+
+/// The built-in monitors.
+pub const TEMPLATES: [Template; 6] = [Template {
+                                          name: "Hour",
+                                          line: r"docWriteTime=(?P<watermark>[^}]+)}",
+                                          batch: None,
+                                          paths: ["xxxx/hour.log*", "xxxx/hour_prod.log*"],
+                                      },
+                                      Template {
+                                          name: "Batch",
+                                          line: "committed",
+                                          batch: Some(r"Context contains (?P<total>\d+)"),
+                                          paths: ["xxx/test/logs/batch.log*",
+                                                  "xxx/prod/logs/batch.log*"],
+                                      },
+                                      Template {
+                                          name: "V1Publisher",
+                                          line: r"DocWriteTime=(?P<watermark>[^,]+),",
+                                          batch: None,
+                                          paths: ["xxx/test/logs/v1_publisher.log*",
+                                                  "xxx/prod/logs/v1_publisher.log*"],
+                                      },
+                                      Template {
+                                          name: "NgConsumer",
+                                          line: r"timestamp=(?P<watermark>.+?)eventId=(?P<id>\d+)",
+                                          batch: None,
+                                          paths: ["xxx/test/logs/ng_consumer.log*",
+                                                  "xxx/prod/logs/ng_consumer.log*"],
+                                      },
+                                      Template {
+                                          name: "NgPublisher",
+                                          line: r"docWriteTime=(?P<watermark>[^}]+)}",
+                                          batch: None,
+                                          paths: ["xxx/test/logs/ng_publisher.log*",
+                                                  "xxx/prod/logs/ng_publisher.log*"],
+                                      },
+                                      Template {
+                                          name: "NgTrimmer",
+                                          line: "committed deletes",
+                                          batch: None,
+                                          paths: ["xxx/test/logs/ng_trimmer.log*",
+                                                  "xxx/prod/logs/ng_trimmer.log*"],
+                                      }];
+
+/// Returns the template by its name, the case is ignored.
+pub fn find(name: &str) -> Option<&'static Template> {
+    TEMPLATES.iter().find(|t| t.name.eq_ignore_ascii_case(name))
+}
+
+impl Template {
+    pub fn parser<'a>(&self) -> LogParser<'a> {
+        LogParser::new_batch(Regex::new(self.line).unwrap(),
+                             self.batch.map(|b| Regex::new(b).unwrap()))
+    }
+
+    pub fn path(&self, prod: bool) -> &'static str {
+        self.paths[prod as usize]
+    }
+}
@@ -0,0 +1,134 @@
+use fnv::FnvHashMap;
+
+/// Upper limit of the values counted by a `TopK`. A value seen more than 1/CAPACITY of the total
+/// is always kept, whatever the number of distinct values.
+const CAPACITY: usize = 100;
+
+/// `TopK` finds the most frequent values of a stream with bounded memory (space-saving). When
+/// full, a new value replaces the least counted one and inherits its count, which is then the
+/// possible overestimation of the new value.
+#[derive(Debug, Clone, Default)]
+pub struct TopK {
+    /// value -> (count, error)
+    counters: FnvHashMap<String, (u64, u64)>,
+    total: u64,
+}
+
+impl TopK {
+    pub fn is_empty(&self) -> bool {
+        self.counters.is_empty()
+    }
+
+    pub fn insert(&mut self, value: &str, weight: u64) {
+        self.total += weight;
+        if let Some(counter) = self.counters.get_mut(value) {
+            counter.0 += weight;
+            return;
+        }
+        let (count, error) = if self.counters.len() < CAPACITY {
+            (weight, 0)
+        } else {
+            let min = self.evict();
+            (min + weight, min)
+        };
+        self.counters.insert(value.to_owned(), (count, error));
+    }
+
+    pub fn merge(&mut self, other: &TopK) {
+        self.total += other.total;
+        for (value, &(count, error)) in &other.counters {
+            let counter = self.counters.entry(value.clone()).or_insert((0, 0));
+            counter.0 += count;
+            counter.1 += error;
+        }
+        // the evicted counts are added to the next lowest, so the counts still sum up to the total
+        while self.counters.len() > CAPACITY {
+            let min = self.evict();
+            // the evicted value may be any of the merged ones, so its count is also an error
+            let lowest = self.lowest().1;
+            lowest.0 += min;
+            lowest.1 += min;
+        }
+    }
+
+    /// Returns the `n` most counted values with their count and possible overestimation, highest
+    /// first.
+    pub fn top(&self, n: usize) -> Vec<(&str, u64, u64)> {
+        let mut top: Vec<_> = self.counters
+                                  .iter()
+                                  .map(|(value, &(count, error))| (value.as_str(), count, error))
+                                  .collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        top.truncate(n);
+        top
+    }
+
+    /// Returns the total count of the values not in the `n` most counted.
+    pub fn other(&self, n: usize) -> u64 {
+        self.total - self.top(n).iter().map(|t| t.1).sum::<u64>()
+    }
+
+    fn lowest(&mut self) -> (&String, &mut (u64, u64)) {
+        self.counters.iter_mut().min_by(|a, b| (a.1).0.cmp(&(b.1).0).then(b.0.cmp(a.0))).unwrap()
+    }
+
+    /// removes the least counted value, returns its count
+    fn evict(&mut self) -> u64 {
+        let value = self.lowest().0.clone();
+        self.counters.remove(&value).map_or(0, |c| c.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_count_top_values() {
+        let mut top = TopK::default();
+        assert!(top.is_empty());
+        for &(value, weight) in &[("a", 5), ("b", 3), ("c", 1), ("a", 2), ("d", 0)] {
+            top.insert(value, weight);
+        }
+        assert_eq!(top.top(2), [("a", 7, 0), ("b", 3, 0)]);
+        assert_eq!(top.other(2), 1);
+        assert_eq!(top.other(10), 0);
+    }
+
+    #[test]
+    fn can_keep_heavy_hitters() {
+        let mut top = TopK::default();
+        for i in 0..10 * CAPACITY {
+            top.insert(&format!("rare{}", i), 1);
+            if i % 4 == 0 {
+                top.insert("heavy", 1);
+            }
+        }
+        assert_eq!(top.counters.len(), CAPACITY);
+        let first = top.top(1)[0];
+        assert_eq!(first.0, "heavy");
+        // never underestimated
+        assert!(first.1 >= 250 && first.1 - first.2 <= 250);
+
+        let mut merged = TopK::default();
+        merged.merge(&top);
+        merged.merge(&top);
+        assert_eq!(merged.counters.len(), CAPACITY);
+        assert_eq!(merged.top(1)[0].0, "heavy");
+        assert_eq!(merged.other(0), 2 * top.other(0));
+    }
+
+    #[test]
+    fn can_merge_errors() {
+        let (mut left, mut right) = (TopK::default(), TopK::default());
+        for i in 0..CAPACITY {
+            left.insert(&format!("left{}", i), 1);
+            right.insert(&format!("right{}", i), 1);
+        }
+        left.merge(&right);
+        assert_eq!(left.counters.len(), CAPACITY);
+        assert_eq!(left.other(0), 2 * CAPACITY as u64);
+        // every value was seen once, the evicted counts are overestimation
+        assert!(left.counters.values().all(|c| c.0 - c.1 <= 1));
+    }
+}